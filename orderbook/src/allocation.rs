@@ -0,0 +1,198 @@
+use core::fmt;
+
+/// Decides how an incoming order's quantity is shared among the orders resting at the
+/// price level it trades against. Displays as `parse_allocation` reads it.
+pub trait AllocationPolicy: fmt::Debug + fmt::Display + Send {
+    /// Splits `quantity` among the resting orders, given as their displayed sizes in time
+    /// priority. Returns what each order gets, in the same order; the allocations add up
+    /// to `quantity` or to everything displayed, whichever is smaller.
+    fn allocate(&self, quantity: usize, resting: &[usize]) -> Vec<usize>;
+}
+
+/// Price-time priority: the oldest order fills first, then the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Fifo;
+
+impl fmt::Display for Fifo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fifo")
+    }
+}
+
+impl AllocationPolicy for Fifo {
+    fn allocate(&self, quantity: usize, resting: &[usize]) -> Vec<usize> {
+        let mut left = quantity;
+        resting
+            .iter()
+            .map(|&size| {
+                let allocation = left.min(size);
+                left -= allocation;
+                allocation
+            })
+            .collect()
+    }
+}
+
+/// Shares the quantity in proportion to the size of each order. Optionally the oldest
+/// order at the level is filled first as the top order, and shares below a minimum are
+/// dropped. Shares are rounded down, and whatever rounding and dropped shares leave over
+/// goes to the orders in time priority, so the outcome only depends on the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProRata {
+    top_order: bool,
+    min_allocation: usize,
+}
+
+impl ProRata {
+    pub fn new() -> Self {
+        ProRata::default()
+    }
+
+    pub fn with_top_order(mut self) -> Self {
+        self.top_order = true;
+        self
+    }
+
+    /// Smallest share given out pro rata; smaller ones go to the time priority round.
+    pub fn with_min_allocation(mut self, min_allocation: usize) -> Self {
+        self.min_allocation = min_allocation;
+        self
+    }
+
+    pub fn top_order(&self) -> bool {
+        self.top_order
+    }
+
+    pub fn min_allocation(&self) -> usize {
+        self.min_allocation
+    }
+}
+
+impl fmt::Display for ProRata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "prorata")?;
+        if self.top_order {
+            write!(f, "/top")?;
+        }
+        if self.min_allocation > 0 {
+            write!(f, "/min={}", self.min_allocation)?;
+        }
+        Ok(())
+    }
+}
+
+impl AllocationPolicy for ProRata {
+    fn allocate(&self, quantity: usize, resting: &[usize]) -> Vec<usize> {
+        let mut allocations = vec![0; resting.len()];
+        let mut left = quantity.min(resting.iter().sum());
+
+        if self.top_order && !resting.is_empty() {
+            allocations[0] = left.min(resting[0]);
+            left -= allocations[0];
+        }
+
+        let open: Vec<usize> = resting.iter().zip(&allocations).map(|(size, allocated)| size - allocated).collect();
+        let total: usize = open.iter().sum();
+        if left > 0 && total > 0 {
+            let pool = left as u128;
+            for (allocation, &size) in allocations.iter_mut().zip(&open) {
+                let share = (pool * size as u128 / total as u128) as usize;
+                if share >= self.min_allocation {
+                    *allocation += share;
+                    left -= share;
+                }
+            }
+        }
+
+        for (allocation, &size) in allocations.iter_mut().zip(resting) {
+            let extra = left.min(size - *allocation);
+            *allocation += extra;
+            left -= extra;
+        }
+        allocations
+    }
+}
+
+/// Parses the matching algorithm of an instrument: `fifo`, or `prorata` optionally
+/// followed by `/top` for top-order priority and `/min=<lots>` for a minimum allocation,
+/// e.g. `prorata/top/min=2`.
+pub fn parse_allocation(field: &str) -> Option<Box<dyn AllocationPolicy>> {
+    let mut parts = field.split('/');
+    match parts.next()? {
+        "fifo" if parts.next().is_none() => Some(Box::new(Fifo)),
+        "prorata" => {
+            let mut pro_rata = ProRata::new();
+            for part in parts {
+                match part.split_once('=') {
+                    None if part == "top" => pro_rata = pro_rata.with_top_order(),
+                    Some(("min", lots)) => pro_rata = pro_rata.with_min_allocation(lots.parse().ok()?),
+                    _ => return None,
+                }
+            }
+            Some(Box::new(pro_rata))
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounding_leftovers_go_in_time_priority() {
+        // 3 lots each, with the one left over to the oldest.
+        assert_eq!(ProRata::new().allocate(10, &[5, 5, 5]), vec![4, 3, 3]);
+        // Shares of 4.2, 2.1 and 0.7 round down to 4, 2 and 0.
+        assert_eq!(ProRata::new().allocate(7, &[6, 3, 1]), vec![5, 2, 0]);
+        // Nothing to share out pro rata at all.
+        assert_eq!(ProRata::new().allocate(2, &[1, 1, 1]), vec![1, 1, 0]);
+    }
+
+    #[test]
+    fn never_more_than_displayed() {
+        assert_eq!(ProRata::new().allocate(20, &[3, 4]), vec![3, 4]);
+        assert_eq!(ProRata::new().with_top_order().with_min_allocation(5).allocate(20, &[3, 4]), vec![3, 4]);
+        assert!(ProRata::new().allocate(5, &[]).is_empty());
+    }
+
+    #[test]
+    fn top_order_fills_first() {
+        // The top order takes 4, then 6 are shared 2.25 and 3.75 among the rest.
+        assert_eq!(ProRata::new().with_top_order().allocate(10, &[4, 6, 10]), vec![4, 3, 3]);
+        assert_eq!(ProRata::new().with_top_order().allocate(3, &[5, 5]), vec![3, 0]);
+    }
+
+    #[test]
+    fn shares_below_min_allocation_dropped() {
+        // Shares of 5, 2.5 and 2.5: the small ones go back to the time priority round.
+        assert_eq!(ProRata::new().with_min_allocation(3).allocate(10, &[10, 5, 5]), vec![10, 0, 0]);
+        assert_eq!(ProRata::new().with_min_allocation(2).allocate(10, &[10, 5, 5]), vec![6, 2, 2]);
+    }
+
+    #[test]
+    fn outcome_depends_only_on_the_queue() {
+        let pro_rata = ProRata::new().with_top_order().with_min_allocation(2);
+        let resting = [7, 3, 9, 1, 4];
+        let allocations = pro_rata.allocate(13, &resting);
+        assert_eq!(allocations.iter().sum::<usize>(), 13);
+        for _ in 0..10 {
+            assert_eq!(pro_rata.allocate(13, &resting), allocations);
+        }
+        // Equal orders: the leftover goes to the oldest.
+        assert_eq!(ProRata::new().allocate(1, &[2, 2, 2]), vec![1, 0, 0]);
+        assert_eq!(ProRata::new().allocate(4, &[2, 2, 2]), vec![2, 1, 1]);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_allocation("fifo").unwrap().allocate(5, &[3, 3]), vec![3, 2]);
+        assert_eq!(parse_allocation("prorata/top/min=2").unwrap().allocate(10, &[4, 6, 10]), vec![4, 3, 3]);
+        assert!(parse_allocation("fifo/top").is_none());
+        assert!(parse_allocation("prorata/min=x").is_none());
+        assert!(parse_allocation("lifo").is_none());
+        for field in ["fifo", "prorata", "prorata/top", "prorata/min=3", "prorata/top/min=2"] {
+            assert_eq!(parse_allocation(field).unwrap().to_string(), field);
+        }
+    }
+}
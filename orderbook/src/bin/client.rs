@@ -3,10 +3,71 @@ use tokio::{
     net::TcpStream,
 };
 use std::io::Write;
+use orderbook::price::Price;
+
+/// Checks the optional trailing fields of an order: a time in force keyword or flag from
+/// `allowed` and `key=value` settings, which are left for the server to validate.
+fn validate_options(fields: &[&str], allowed: &[&str]) -> Result<String, String> {
+    let mut options = String::new();
+    for field in fields {
+        // Values such as the account are case sensitive, keywords are not.
+        let field = if field.contains('=') { field.to_string() } else { field.to_lowercase() };
+        if !field.contains('=') && !allowed.contains(&field.as_str()) {
+            return Err(format!("Invalid option: choose from {}", allowed.join(", ")));
+        }
+        options.push(' ');
+        options.push_str(&field);
+    }
+    Ok(options)
+}
 
 pub fn validate_input(input: &str) -> Result<String, String> {
-    let parts: Vec<&str> = input.trim().split_whitespace().collect();
-    if parts.len() < 4 {
+    let parts: Vec<&str> = input.split_whitespace().collect();
+
+    if parts.first().is_some_and(|p| p.eq_ignore_ascii_case("cancel")) {
+        if parts.len() != 3 {
+            return Err("Format cancel: cancel <symbol> <order_id>".into());
+        }
+        let order_id: u64 = parts[2]
+            .parse()
+            .map_err(|_| "Invalid order id".to_string())?;
+        return Ok(format!("cancel {} {}", parts[1], order_id));
+    }
+
+    if parts.first().is_some_and(|p| p.eq_ignore_ascii_case("amend")) {
+        if parts.len() != 5 {
+            return Err("Format amend: amend <symbol> <order_id> <new_price> <new_qty>".into());
+        }
+        let order_id: u64 = parts[2]
+            .parse()
+            .map_err(|_| "Invalid order id".to_string())?;
+        let price: Price = parts[3]
+            .parse()
+            .map_err(|_| "Invalid price".to_string())?;
+        let qty: usize = parts[4]
+            .parse()
+            .map_err(|_| "Invalid quantity".to_string())?;
+        return Ok(format!("amend {} {} {} {}", parts[1], order_id, price, qty));
+    }
+
+    if parts.first().is_some_and(|p| p.eq_ignore_ascii_case("book")) {
+        if parts.len() != 3 {
+            return Err("Format book: book <symbol> <levels>".into());
+        }
+        let levels: usize = parts[2]
+            .parse()
+            .map_err(|_| "Invalid number of levels".to_string())?;
+        return Ok(format!("book {} {}", parts[1], levels));
+    }
+
+    if parts.first().is_some_and(|p| p.eq_ignore_ascii_case("halt") || p.eq_ignore_ascii_case("resume")) {
+        if parts.len() != 2 {
+            return Err("Format halt/resume: halt <symbol> or resume <symbol>".into());
+        }
+        return Ok(format!("{} {}", parts[0].to_lowercase(), parts[1]));
+    }
+
+    if parts.len() < 5 {
         return Err("Comanda prea scurtă".into());
     }
 
@@ -15,43 +76,88 @@ pub fn validate_input(input: &str) -> Result<String, String> {
         "sell" => "sell",
         _ => return Err("invalid side: choose 'buy' or 'sell'".into()),
     };
+    let symbol = parts[2];
 
     match parts[1].to_lowercase().as_str() {
         "market" => {
-            if parts.len() != 4 {
-                return Err("Format market: buy/sell market <qty> <order_id>".into());
+            if parts.len() < 5 {
+                return Err("Format market: buy/sell market <symbol> <qty> <order_id> [ioc|fok] [protect=<price>|protect=<pct>%] [account=<id> [stp=cn|co|cb|dc]]".into());
             }
-            let qty: usize = parts[2]
+            let qty: usize = parts[3]
                 .parse()
                 .map_err(|_| "Invalid quantity".to_string())?;
-            Ok(format!("{} market {} id:{}", side, qty, parts[3].to_string()))
+            let options = validate_options(&parts[5..], &["ioc", "fok"])?;
+            Ok(format!("{} market {} {} id:{}{}", side, symbol, qty, parts[4], options))
         }
 
         "limit" => {
-            if parts.len() != 5 {
-                return Err("Format limit: buy/sell limit <price> <qty> <order_id>".into());
+            if parts.len() < 6 {
+                return Err("Format limit: buy/sell limit <symbol> <price> <qty> <order_id> [gtc|day|ioc|fok] [display=<qty>] [postonly|postonly=reprice] [hidden] [account=<id> [stp=cn|co|cb|dc]]".into());
             }
-            let price: usize = parts[2]
+            let price: Price = parts[3]
                 .parse()
                 .map_err(|_| "Invalid price".to_string())?;
-            let qty: usize = parts[3]
+            let qty: usize = parts[4]
+                .parse()
+                .map_err(|_| "Invalid quantity".to_string())?;
+            let options = validate_options(&parts[6..], &["gtc", "day", "ioc", "fok", "postonly", "hidden"])?;
+            Ok(format!("{} limit {} {} {} id:{}{}", side, symbol, price, qty, parts[5], options))
+        }
+
+        "stop" => {
+            if parts.len() < 6 {
+                return Err("Format stop: buy/sell stop <symbol> <trigger> <qty> <order_id> [ioc|fok] [protect=<price>|protect=<pct>%] [account=<id> [stp=cn|co|cb|dc]]".into());
+            }
+            let trigger: Price = parts[3]
+                .parse()
+                .map_err(|_| "Invalid trigger price".to_string())?;
+            let qty: usize = parts[4]
+                .parse()
+                .map_err(|_| "Invalid quantity".to_string())?;
+            let options = validate_options(&parts[6..], &["ioc", "fok"])?;
+            Ok(format!("{} stop {} {} {} id:{}{}", side, symbol, trigger, qty, parts[5], options))
+        }
+
+        "stoplimit" => {
+            if parts.len() < 7 {
+                return Err("Format stoplimit: buy/sell stoplimit <symbol> <trigger> <price> <qty> <order_id> [gtc|day|ioc|fok] [display=<qty>] [postonly|postonly=reprice] [hidden] [account=<id> [stp=cn|co|cb|dc]]".into());
+            }
+            let trigger: Price = parts[3]
+                .parse()
+                .map_err(|_| "Invalid trigger price".to_string())?;
+            let price: Price = parts[4]
+                .parse()
+                .map_err(|_| "Invalid price".to_string())?;
+            let qty: usize = parts[5]
                 .parse()
                 .map_err(|_| "Invalid quantity".to_string())?;
-            Ok(format!("{} limit {} {} id:{}", side, price, qty, parts[4].to_string()))
+            let options = validate_options(&parts[7..], &["gtc", "day", "ioc", "fok", "postonly", "hidden"])?;
+            Ok(format!("{} stoplimit {} {} {} {} id:{}{}", side, symbol, trigger, price, qty, parts[6], options))
         }
 
-        _ => Err("Invalid order type: choose 'market' or 'limit'".into()),
+        _ => Err("Invalid order type: choose 'market', 'limit', 'stop' or 'stoplimit'".into()),
     }
 }
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let stream = TcpStream::connect("127.0.0.1:8080").await?;
+    // Operators connect to the operator port instead, e.g. SERVER_ADDR=127.0.0.1:8081.
+    let addr = std::env::var("SERVER_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".to_string());
+    let stream = TcpStream::connect(&addr).await?;
 
     println!("Connected to server!");
 
     let (mut stream_reader, mut stream_writer) = stream.into_split();
 
+    // The operator port asks for the server's OPERATOR_SECRET first.
+    if let Ok(secret) = std::env::var("OPERATOR_SECRET") {
+        stream_writer.write_all(format!("auth {secret}\n").as_bytes()).await?;
+    }
+    // With LOGIN the orders belong to that name, so they can be managed after reconnecting.
+    if let Ok(name) = std::env::var("LOGIN") {
+        stream_writer.write_all(format!("login {name}\n").as_bytes()).await?;
+    }
+
     let mut stdin_reader = BufReader::new(io::stdin());
     let mut input_line = String::new();
 
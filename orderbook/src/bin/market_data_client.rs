@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+/// Value of the `key=value` field `key` in a feed line.
+fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.split_whitespace()
+        .find_map(|f| f.strip_prefix(key)?.strip_prefix('='))
+}
+
+/// Feed a line belongs to, `l2` for levels and trades or `l3` for orders.
+fn feed_of(line: &str) -> &'static str {
+    if line.starts_with("order") { "l3" } else { "l2" }
+}
+
+/// Subscribes to the feeds given as arguments, `<symbol>` for levels and trades or
+/// `<symbol>:l3` for orders (AAPL by default), and prints them. Sequence numbers are
+/// checked per feed: after a gap the updates are ignored until a fresh snapshot arrives,
+/// and only the updates following that snapshot are shown.
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let mut feeds: Vec<(String, &'static str)> = std::env::args()
+        .skip(1)
+        .map(|arg| {
+            let arg = arg.to_uppercase();
+            match arg.split_once(':') {
+                Some((symbol, "L3")) => (symbol.to_string(), "l3"),
+                _ => (arg, "l2"),
+            }
+        })
+        .collect();
+    if feeds.is_empty() {
+        feeds.push(("AAPL".to_string(), "l2"));
+    }
+
+    let addr = std::env::var("MARKET_DATA_ADDR").unwrap_or_else(|_| "127.0.0.1:9000".to_string());
+    let stream = TcpStream::connect(&addr).await?;
+    println!("Connected to market data feed at {addr}");
+
+    let (reader, mut writer) = stream.into_split();
+    for (symbol, feed) in &feeds {
+        writer.write_all(format!("subscribe {symbol} {feed}\n").as_bytes()).await?;
+    }
+
+    // Last sequence number shown per feed, None while waiting for a snapshot.
+    let mut last_seq: HashMap<(String, &'static str), Option<u64>> = feeds.into_iter().map(|feed| (feed, None)).collect();
+
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let seq = field(&line, "seq").and_then(|seq| seq.parse::<u64>().ok());
+        let (Some(symbol), Some(seq)) = (field(&line, "symbol"), seq) else {
+            println!("{line}");
+            continue;
+        };
+        let feed = feed_of(&line);
+        let Some(last) = last_seq.get_mut(&(symbol.to_string(), feed)) else {
+            continue;
+        };
+
+        if line.starts_with("book ") || line.starts_with("orders ") {
+            *last = Some(seq);
+            println!("{line}");
+            continue;
+        }
+        match *last {
+            Some(last_shown) if seq == last_shown + 1 => {
+                *last = Some(seq);
+                println!("{line}");
+            },
+            Some(last_shown) if seq > last_shown + 1 => {
+                eprintln!("Gap in {symbol} {feed}: expected seq {}, received {seq}, requesting a snapshot", last_shown + 1);
+                *last = None;
+                writer.write_all(format!("snapshot {symbol} {feed}\n").as_bytes()).await?;
+            },
+            // Already part of the last snapshot, or sent before the one still on its way.
+            _ => {},
+        }
+    }
+
+    println!("Connection terminated by the server");
+    Ok(())
+}
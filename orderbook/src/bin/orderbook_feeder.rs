@@ -16,7 +16,9 @@ pub async fn main() -> io::Result<()> {
         let price: usize = rng.random_range(80..=150);
         let qty: usize = rng.random_range(1..=10);
 
-        let cmd = format!("{side} limit {price} {qty} 1\n");
+        let symbol = ["AAPL", "MSFT", "TSLA"][rng.random_range(0..3)];
+
+        let cmd = format!("{side} limit {symbol} {price} {qty} 1\n");
 
         let order_task = tokio::spawn(async move {
             if let Ok(mut conn) = TcpStream::connect("127.0.0.1:8080").await {
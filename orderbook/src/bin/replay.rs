@@ -0,0 +1,90 @@
+use orderbook::{
+    client_handler::{ClientMessage, Logins},
+    instrument::load_instruments,
+    journal::{read_journal, replay, BookConfig},
+    market_data::{Feed, MarketDataEvent, QueueBook},
+    orderbook::OrderBook,
+    protocol::{serialize_depth, serialize_market_data, serialize_order_snapshot, serialize_report, serialize_working_order},
+    session::{halt_policy_from_env, volatility_auction_from_env},
+};
+use std::{io, path::Path};
+use tokio::sync::mpsc;
+
+/// Replays the journal of a book offline and prints the trades it produced, the
+/// execution reports with `--reports`, and the book it ended with: its market data views,
+/// then every working order, hidden orders, stops and orders held during a halt included,
+/// and the state the next request would meet:
+///
+/// ```text
+/// cargo run --bin replay journal/AAPL.journal [--reports]
+/// ```
+///
+/// The book is set up from the same `INSTRUMENTS`, `VOLATILITY_AUCTION_SECS` and
+/// `HALT_ORDERS` as the server, and the replay refuses to start unless that gives the
+/// settings recorded in the journal.
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let reports = args.iter().any(|arg| arg == "--reports");
+    let Some(path) = args.iter().find(|arg| !arg.starts_with("--")) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: replay <journal> [--reports]"));
+    };
+
+    let contents = read_journal(Path::new(path))?;
+    let symbol = contents.symbol().clone();
+
+    let instruments_path = std::env::var("INSTRUMENTS").unwrap_or_else(|_| "instruments.csv".to_string());
+    let Some((instrument, allocation)) = load_instruments(&instruments_path)?
+        .into_iter()
+        .find(|(instrument, _)| *instrument.symbol() == symbol) else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{symbol} is not listed in {instruments_path}")));
+    };
+    let mut orderbook = OrderBook::new(instrument)
+        .with_volatility_auction(volatility_auction_from_env()?)
+        .with_halt_policy(halt_policy_from_env()?)
+        .with_allocation(allocation);
+    contents.check_config(&BookConfig::of(&orderbook))?;
+    let mut queues = QueueBook::new(symbol.clone());
+
+    let (tx_reports, mut rx_reports) = mpsc::unbounded_channel();
+    let (tx_market_data, mut rx_market_data) = mpsc::unbounded_channel();
+    let logins = Logins::new();
+
+    println!("Replaying {} records of {symbol} from {path}", contents.records().len());
+
+    // One record at a time, so that what each one caused is printed under it.
+    for record in contents.records() {
+        replay(&mut orderbook, std::slice::from_ref(record), &logins, &tx_reports, &tx_market_data)?;
+
+        while let Ok(message) = rx_reports.try_recv() {
+            match message {
+                ClientMessage::Report(report) if reports => println!("journal_seq={} {}", record.seq(), serialize_report(&report)),
+                _ => {},
+            }
+        }
+        while let Ok(update) = rx_market_data.try_recv() {
+            if update.feed() == Feed::Orders {
+                if let Err(gap) = queues.apply(&update) {
+                    eprintln!("Order feed of {symbol} out of sequence: {gap}");
+                }
+            } else if let MarketDataEvent::Trade(_) = update.event() {
+                println!("journal_seq={} {}", record.seq(), serialize_market_data(&update));
+            }
+        }
+    }
+
+    println!("{}", serialize_depth(&orderbook.depth(usize::MAX)));
+    println!("{}", serialize_order_snapshot(&queues.snapshot()));
+    for working in orderbook.working() {
+        println!("{}", serialize_working_order(&working));
+    }
+    println!(
+        "state phase={} last_price={} volatility_auction_end={} next_order_id={} next_exec_id={} next_trade_id={}",
+        orderbook.phase().code(),
+        orderbook.last_price().map(|price| price.to_string()).unwrap_or_default(),
+        orderbook.volatility_auction_end().map(|end| end.to_rfc3339()).unwrap_or_default(),
+        contents.last_order_id() + 1,
+        orderbook.next_exec_id(),
+        orderbook.next_trade_id(),
+    );
+    Ok(())
+}
@@ -1,45 +1,188 @@
-use chrono::Utc;
-use orderbook::{client_handler::Client, orders::*};
+use chrono::{DateTime, Days, NaiveTime, Utc};
+use orderbook::{
+    client_handler::{Client, ClientMessage, Logins},
+    execution_report::{CancelReason, ExecutionDetails, ExecutionReport, RejectReason},
+    instrument::load_instruments,
+    journal::{replay, BookConfig, FsyncPolicy, Journal, JournalEntry},
+    market_data::{Feed, MarketData},
+    orderbook::OrderBook,
+    orders::*,
+    price::Price,
+    protocol::{expect_fields, parse_login, parse_operator_request, parse_request, parse_symbol, serialize_depth, serialize_market_data, serialize_order_snapshot, serialize_report, ParseError},
+    publisher::{FeedMessage, FeedRequest, MarketDataPublisher},
+    registry::{BookRegistry, ClientRequest},
+    session::{halt_policy_from_env, volatility_auction_from_env, TradingPhase, TradingSchedule},
+};
 use tokio::{
-    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader}, net::{TcpListener, TcpStream}, sync::mpsc
+    io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader}, net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpListener, TcpStream}, sync::mpsc
 };
-use std::{net::SocketAddr, sync::{atomic::{AtomicU64, Ordering::Relaxed}, Arc}, time::Instant};
+use std::{net::SocketAddr, path::Path, sync::{atomic::{AtomicU64, Ordering::Relaxed}, Arc}, time::Instant};
 
-pub fn create_order(input: &str, client: Client) -> Option<Orders> {
-    let parts: Vec<&str> = input.trim().split_whitespace().collect();
+/// Feed messages queued per market data subscriber before further ones are dropped.
+const SUBSCRIBER_QUEUE: usize = 1024;
 
-    let side = match parts[0].to_lowercase().as_str() {
-        "buy"  => Some(MarketSide::Bid),
-        "sell" => Some(MarketSide::Ask),
-        _ => None,
-    };
+/// Next time the trading session closes, i.e. today at `session_end` (UTC) if that is
+/// still ahead of `now`, otherwise tomorrow.
+fn next_session_end(now: DateTime<Utc>, session_end: NaiveTime) -> DateTime<Utc> {
+    let today = now.date_naive().and_time(session_end).and_utc();
+    if today > now {
+        today
+    } else {
+        today + Days::new(1)
+    }
+}
 
-    match parts[1].to_lowercase().as_str() {
-        "market" => {
-            let qty: usize = parts[2].parse().unwrap();
-            Some(MarketOrder::new(Utc::now(), qty, 0, side.unwrap(), client, parts[3].to_string()).into())
-        }
+/// Reject for a line that never reached the book, so it carries no exchange or exec id.
+fn parse_reject(error: ParseError) -> ExecutionReport {
+    let details = ExecutionDetails::new(0, 0, String::new(), String::new(), 0, Price::default(), 0, 0, Utc::now());
+    ExecutionReport::Rejected(details, RejectReason::Malformed(error))
+}
 
-        "limit" => {
-            let price: usize = parts[2].parse().unwrap();
-            let qty: usize = parts[3].parse().unwrap();
-            Some(LimitOrder::new(Utc::now(), qty, 0, side.unwrap(), price, client, parts[4].to_string()).into())
-        },
+fn serialize_feed_message(message: &FeedMessage) -> String {
+    match message {
+        FeedMessage::Snapshot(depth) => serialize_depth(depth),
+        FeedMessage::OrderSnapshot(snapshot) => serialize_order_snapshot(snapshot),
+        FeedMessage::Update(update) => serialize_market_data(update),
+        FeedMessage::Rejected(symbol, reason) => format!("rejected symbol={} reason={}", symbol, reason.code()),
+    }
+}
 
-        _ => None,
+/// Level feed (`l2`, the default) or order feed (`l3`).
+fn parse_feed(field: Option<&&str>) -> Result<Feed, ParseError> {
+    match field.map(|f| f.to_lowercase()).as_deref() {
+        None | Some("l2") => Ok(Feed::Levels),
+        Some("l3") => Ok(Feed::Orders),
+        Some(_) => Err(ParseError::InvalidOption),
     }
 }
 
-async fn handle_client(stream: TcpStream, sockaddr: SocketAddr, tx_ob: mpsc::UnboundedSender<Orders>) -> io::Result<()> {
+/// Parses a subscriber line: `subscribe <symbol> [l2|l3]` starts a feed of a symbol with
+/// a snapshot, `snapshot <symbol> [l2|l3]` asks for a fresh one after a gap.
+fn parse_feed_request(input: &str, sockaddr: SocketAddr) -> Result<FeedRequest, ParseError> {
+    let parts: Vec<&str> = input.split_whitespace().collect();
+    let command = parts.first().ok_or(ParseError::UnknownCommand)?.to_lowercase();
+
+    match command.as_str() {
+        "subscribe" => {
+            expect_fields(&parts, 2..=3)?;
+            Ok(FeedRequest::Subscribe(sockaddr, parse_symbol(parts[1])?, parse_feed(parts.get(2))?))
+        },
+        "snapshot" => {
+            expect_fields(&parts, 2..=3)?;
+            Ok(FeedRequest::Snapshot(sockaddr, parse_symbol(parts[1])?, parse_feed(parts.get(2))?))
+        },
+        _ => Err(ParseError::UnknownCommand),
+    }
+}
+
+async fn handle_subscriber(stream: TcpStream, sockaddr: SocketAddr, tx_feed: mpsc::UnboundedSender<FeedRequest>) -> io::Result<()> {
     let (reader, mut writer) = stream.into_split();
 
     let mut buf = BufReader::new(reader);
 
-    let (tx, mut rx) = mpsc::channel::<String>(100);
+    let (tx, mut rx) = mpsc::channel::<FeedMessage>(SUBSCRIBER_QUEUE);
 
-    let client = Client::new(tx, sockaddr);
+    if tx_feed.send(FeedRequest::Connect(sockaddr, tx.clone())).is_err() {
+        return Ok(());
+    }
 
-    let socket_reader = async move {
+    let request_reader = async {
+        loop {
+            let mut line = String::new();
+            match buf.read_line(&mut line).await {
+                Ok(0) => {
+                    println!("Subscriber {sockaddr} disconnected");
+                    break;
+                },
+                Ok(_) if line.trim().is_empty() => {},
+                Ok(_) => {
+                    match parse_feed_request(&line, sockaddr) {
+                        Ok(request) => {
+                            if let Err(e) = tx_feed.send(request) {
+                                eprintln!("Error sending request to publisher: {e}");
+                            }
+                        },
+                        Err(e) => {
+                            if let Err(e) = tx.try_send(FeedMessage::Rejected(String::new(), RejectReason::Malformed(e))) {
+                                eprintln!("Error writing to channel: {e}");
+                            }
+                        }
+                    }
+                },
+                Err(e) => {
+                    eprintln!("Error reading from subscriber {sockaddr}: {e}");
+                    break;
+                }
+            }
+        }
+    };
+
+    let feed_writer = async {
+        while let Some(message) = rx.recv().await {
+            let msg = serialize_feed_message(&message);
+            if let Err(e) = writer.write_all(format!("{msg}\n").as_bytes()).await {
+                eprintln!("Error writing to socket: {e}");
+                break;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = request_reader => {},
+        _ = feed_writer => {},
+    }
+
+    let _ = tx_feed.send(FeedRequest::Disconnect(sockaddr));
+    Ok(())
+}
+
+/// Compares secrets in time that does not depend on where they differ.
+fn same_secret(given: &str, secret: &str) -> bool {
+    given.len() == secret.len() && given.bytes().zip(secret.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Serves one operator connection. When the server has a secret, the first line must be
+/// `auth <secret>`, or the connection is rejected and closed; then only halts and resumes
+/// are accepted.
+async fn handle_operator(stream: TcpStream, sockaddr: SocketAddr, tx_ob: mpsc::UnboundedSender<ClientRequest>, secret: Option<Arc<str>>) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut buf = BufReader::new(reader);
+
+    if let Some(secret) = secret {
+        let mut line = String::new();
+        buf.read_line(&mut line).await?;
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let authenticated = match parts.as_slice() {
+            [command, given] => command.eq_ignore_ascii_case("auth") && same_secret(given, &secret),
+            _ => false,
+        };
+        if !authenticated {
+            println!("Operator {sockaddr} failed to authenticate");
+            let reject = serialize_report(&parse_reject(ParseError::Unauthorized));
+            writer.write_all(format!("{reject}\n").as_bytes()).await?;
+            return Ok(());
+        }
+    }
+
+    serve(buf, writer, sockaddr, tx_ob, None, parse_operator_request).await
+}
+
+async fn handle_client(stream: TcpStream, sockaddr: SocketAddr, tx_ob: mpsc::UnboundedSender<ClientRequest>, logins: Logins) -> io::Result<()> {
+    let (reader, writer) = stream.into_split();
+    serve(BufReader::new(reader), writer, sockaddr, tx_ob, Some(logins), parse_request).await
+}
+
+/// Serves a connection, turning each line into a request with `parse`. With `logins`,
+/// the first line may be `login <name>`, after which the orders of the connection belong
+/// to that name rather than to the connection.
+async fn serve(mut buf: BufReader<OwnedReadHalf>, mut writer: OwnedWriteHalf, sockaddr: SocketAddr, tx_ob: mpsc::UnboundedSender<ClientRequest>, logins: Option<Logins>, parse: fn(&str, Client, DateTime<Utc>) -> Result<Orders, ParseError>) -> io::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ClientMessage>();
+
+    let mut client = Client::new(tx.clone(), sockaddr);
+    let mut login: Option<String> = None;
+    let mut first_line = true;
+
+    let socket_reader = async {
         loop {
             let mut line = String::new();
             match buf.read_line(&mut line).await {
@@ -47,10 +190,35 @@ async fn handle_client(stream: TcpStream, sockaddr: SocketAddr, tx_ob: mpsc::Unb
                     println!("Connection terminated by client {sockaddr}");
                     break;
                 },
+                Ok(_) if line.trim().is_empty() => {},
                 Ok(_) => {
-                    let order = create_order(&line, client.clone()).unwrap();
-                    if let Err(e) = tx_ob.send(order) {
-                        eprintln!("Error sending order to OrderBook: {e}");
+                    let request = match (parse_login(&line), &logins) {
+                        (Some(Ok(name)), Some(logins)) if first_line => match logins.login(name, sockaddr, tx.clone()) {
+                            Some(logged_in) => {
+                                println!("Client {sockaddr} logged in as {name}");
+                                client = logged_in;
+                                login = Some(name.to_string());
+                                None
+                            },
+                            None => Some(Err(ParseError::LoginInUse)),
+                        },
+                        (Some(_), Some(_)) => Some(Err(ParseError::InvalidLogin)),
+                        _ => Some(parse(&line, client.clone(), Utc::now())),
+                    };
+                    first_line = false;
+                    match request {
+                        None => {},
+                        Some(Ok(order)) => {
+                            if let Err(e) = tx_ob.send(order.into()) {
+                                eprintln!("Error sending order to OrderBook: {e}");
+                            }
+                        },
+                        Some(Err(e)) => {
+                            println!("Rejected line from {sockaddr}: {:?} ({e})", line.trim());
+                            if let Err(e) = client.tx().send(parse_reject(e).into()) {
+                                eprintln!("Error writing to channel: {e}");
+                            }
+                        }
                     }
                     line.clear();
                 },
@@ -68,18 +236,17 @@ async fn handle_client(stream: TcpStream, sockaddr: SocketAddr, tx_ob: mpsc::Unb
     };
 
     let channel_reader_socket_writer = async move {
-        loop {
-            match rx.recv().await {
-                Some(msg) => {
-                    if let Err(e) = writer.write_all(format!("{msg}\n").as_bytes()).await {
-                        eprintln!("Error writing to socket: {e}");
-                        break;
-                    } else {
-                        println!("msg written to socket");
-                    }
-                    
-                },
-                None => {}
+        while let Some(message) = rx.recv().await {
+            let msg = match message {
+                ClientMessage::Report(report) => serialize_report(&report),
+                ClientMessage::Depth(depth) => serialize_depth(&depth),
+                ClientMessage::MarketData(update) => serialize_market_data(&update),
+            };
+            if let Err(e) = writer.write_all(format!("{msg}\n").as_bytes()).await {
+                eprintln!("Error writing to socket: {e}");
+                break;
+            } else {
+                println!("msg written to socket");
             }
         }
     };
@@ -89,30 +256,222 @@ async fn handle_client(stream: TcpStream, sockaddr: SocketAddr, tx_ob: mpsc::Unb
         _ = channel_reader_socket_writer => {},
     }
 
+    let _ = tx_ob.send(ClientRequest::Disconnect(sockaddr));
+    if let (Some(logins), Some(name)) = (&logins, &login) {
+        logins.logout(name, sockaddr);
+    }
+
     Ok(())
 }
 
+/// Parses a trading schedule given as the comma separated `HH:MM` start times of the
+/// pre-open, opening auction, continuous trading, closing auction and close.
+fn parse_schedule(s: &str) -> Option<TradingSchedule> {
+    let times = s
+        .split(',')
+        .map(|time| NaiveTime::parse_from_str(time.trim(), "%H:%M").ok())
+        .collect::<Option<Vec<NaiveTime>>>()?;
+    match times[..] {
+        [pre_open, opening_auction, continuous, closing_auction, closed] => TradingSchedule::new(pre_open, opening_auction, continuous, closing_auction, closed),
+        _ => None,
+    }
+}
+
+/// Parses `JOURNAL_FSYNC`: `always`, `never` or a number of records between syncs.
+fn parse_fsync_policy(s: &str) -> Option<FsyncPolicy> {
+    match s {
+        "always" => Some(FsyncPolicy::Always),
+        "never" => Some(FsyncPolicy::Never),
+        _ => s.parse().ok().filter(|&records| records > 0).map(FsyncPolicy::Every),
+    }
+}
+
+/// Stops the server when a book cannot write its journal: what the book did next could
+/// not be recovered.
+fn check_journal<T>(result: io::Result<T>, symbol: &str) {
+    if let Err(e) = result {
+        eprintln!("Error writing the journal of {symbol}: {e}");
+        std::process::exit(1);
+    }
+}
+
+/// Catches up on a session end that went by while the server was down, since `last`,
+/// the time of the last journaled record: the book closes, with a schedule, and DAY
+/// orders expire, both at the time the session ended and journaled first.
+fn close_missed_session(orderbook: &mut OrderBook, journal: &mut Journal, last: DateTime<Utc>, session_end: NaiveTime, schedule: Option<&TradingSchedule>, tx_market_data: &mpsc::UnboundedSender<MarketData>) -> io::Result<()> {
+    let ended = next_session_end(last, schedule.map_or(session_end, TradingSchedule::close));
+    if ended > Utc::now() {
+        return Ok(());
+    }
+
+    println!("{} missed the session end at {ended}, expiring DAY orders", orderbook.symbol());
+    if schedule.is_some() {
+        journal.append(ended, JournalEntry::Phase(TradingPhase::Closed))?;
+        orderbook.set_phase(TradingPhase::Closed, ended, tx_market_data);
+    }
+    journal.append(ended, JournalEntry::ExpireDayOrders)?;
+    orderbook.expire_day_orders(ended, tx_market_data);
+    Ok(())
+}
+
+/// Runs a book on its own thread and returns the channel that feeds it. With a schedule
+/// the book moves through the trading phases and DAY orders expire at the close; without
+/// one it trades continuously and DAY orders expire at every session end. Volatility
+/// auctions end on their own timer either way. Everything the book acts on is written
+/// to its journal first; the thread is the book's alone, so waiting on the disk never
+/// holds up the connections or the other books.
+fn spawn_book(mut orderbook: OrderBook, mut journal: Journal, session_end: NaiveTime, schedule: Option<TradingSchedule>, tx_market_data: mpsc::UnboundedSender<MarketData>, counter: Arc<AtomicU64>) -> io::Result<mpsc::UnboundedSender<Orders>> {
+    let (tx_book, mut rx_book) = mpsc::unbounded_channel::<Orders>();
+    let symbol = orderbook.symbol().clone();
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build()?;
+
+    std::thread::Builder::new().name(format!("book-{symbol}")).spawn(move || runtime.block_on(async move {
+        if let Some(schedule) = &schedule {
+            let now = Utc::now();
+            let phase = schedule.phase_at(now);
+            check_journal(journal.append(now, JournalEntry::Phase(phase)), &symbol);
+            orderbook.set_phase(phase, now, &tx_market_data);
+        }
+        loop {
+            let (at, phase) = match &schedule {
+                Some(schedule) => schedule.next_change(Utc::now()),
+                None => (next_session_end(Utc::now(), session_end), TradingPhase::Continuous),
+            };
+            let change_in = (at - Utc::now()).to_std().unwrap_or_default();
+            let auction_end = orderbook.volatility_auction_end();
+            let auction_ends_in = auction_end
+                .and_then(|end| (end - Utc::now()).to_std().ok())
+                .unwrap_or_default();
+
+            tokio::select! {
+                Some(order) = rx_book.recv() => {
+                    check_journal(journal.append_request(&order), &symbol);
+                    orderbook.handle_order(order, tx_market_data.clone(), counter.clone());
+                },
+                _ = tokio::time::sleep(change_in) => {
+                    if schedule.is_some() {
+                        println!("{symbol} enters phase {}", phase.code());
+                        let now = Utc::now();
+                        check_journal(journal.append(now, JournalEntry::Phase(phase)), &symbol);
+                        orderbook.set_phase(phase, now, &tx_market_data);
+                    }
+                    if schedule.is_none() || phase == TradingPhase::Closed {
+                        println!("Session closed, expiring DAY orders in {symbol}");
+                        let now = Utc::now();
+                        check_journal(journal.append(now, JournalEntry::ExpireDayOrders), &symbol);
+                        orderbook.expire_day_orders(now, &tx_market_data);
+                    }
+                },
+                _ = tokio::time::sleep(auction_ends_in), if auction_end.is_some() => {
+                    println!("Volatility auction over in {symbol}");
+                    let now = Utc::now();
+                    check_journal(journal.append(now, JournalEntry::EndVolatilityAuction), &symbol);
+                    orderbook.end_volatility_auction(now, &tx_market_data);
+                }
+            }
+        }
+    }))?;
+
+    Ok(tx_book)
+}
+
 #[tokio::main]
 async fn main() -> io::Result<()> {
     let listener = TcpListener::bind("127.0.0.1:8080").await?;
 
     println!("Server listening on 127.0.0.1:8080");
 
-    let (tx, mut rx) = mpsc::unbounded_channel::<Orders>();
-    let (tx_price, mut rx_price) = mpsc::unbounded_channel::<usize>();
+    // DAY orders expire at this time every day (UTC), e.g. SESSION_END=21:00.
+    let session_end = std::env::var("SESSION_END")
+        .ok()
+        .and_then(|s| NaiveTime::parse_from_str(&s, "%H:%M").ok())
+        .unwrap_or(NaiveTime::from_hms_opt(22, 0, 0).unwrap());
+
+    // Optional daily trading phases (UTC): pre-open, opening auction, continuous trading,
+    // closing auction and close, e.g. SCHEDULE=07:00,07:50,08:00,16:30,16:35.
+    // Without a schedule the books trade continuously.
+    let schedule = match std::env::var("SCHEDULE") {
+        Ok(s) => match parse_schedule(&s) {
+            Some(schedule) => {
+                println!("Trading phases start daily at {s} UTC, DAY orders expire at the close");
+                Some(schedule)
+            },
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid SCHEDULE {s:?}: expected five increasing HH:MM times"))),
+        },
+        Err(_) => {
+            println!("Trading session ends daily at {session_end} UTC");
+            None
+        },
+    };
+
+    // Length of the volatility auction a circuit breaker starts, e.g. VOLATILITY_AUCTION_SECS=60.
+    let volatility_auction = volatility_auction_from_env()?;
+
+    // What halted books do with new orders and amends: HALT_ORDERS=reject (default) or queue.
+    let halt_policy = halt_policy_from_env()?;
+    println!("Volatility auctions last {}s, halted books {} new orders", volatility_auction.num_seconds(), halt_policy.code());
+
+    // Reference data of the traded instruments, e.g. INSTRUMENTS=instruments.csv.
+    let instruments_path = std::env::var("INSTRUMENTS").unwrap_or_else(|_| "instruments.csv".to_string());
+    let instruments = load_instruments(&instruments_path)?;
+
+    let symbols: Vec<&str> = instruments.iter().map(|(instrument, _)| instrument.symbol().as_str()).collect();
+    println!("Trading symbols: {}", symbols.join(", "));
+
+    // Every book journals its requests to <JOURNAL_DIR>/<symbol>.journal before handling
+    // them, synced to disk per JOURNAL_FSYNC: always (default), never or every n records.
+    let journal_dir = std::env::var("JOURNAL_DIR").unwrap_or_else(|_| "journal".to_string());
+    let fsync = match std::env::var("JOURNAL_FSYNC") {
+        Ok(s) => parse_fsync_policy(&s).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid JOURNAL_FSYNC {s:?}: expected always, never or a number of records")))?,
+        Err(_) => FsyncPolicy::Always,
+    };
+    std::fs::create_dir_all(&journal_dir)?;
+    println!("Journaling to {journal_dir}/ with fsync {fsync:?}");
+
+    let (tx, mut rx) = mpsc::unbounded_channel::<ClientRequest>();
+    let (tx_market_data, mut rx_market_data) = mpsc::unbounded_channel::<MarketData>();
+    let (tx_feed, mut rx_feed) = mpsc::unbounded_channel::<FeedRequest>();
 
     let counter = Arc::new(AtomicU64::new(0));
     let c = counter.clone();
 
     let start = Instant::now();
 
+    // Operators halt and resume trading from here, e.g. OPERATOR_ADDR=127.0.0.1:8081,
+    // authenticating with OPERATOR_SECRET. Without a secret only loopback is allowed.
+    let operator_addr = std::env::var("OPERATOR_ADDR").unwrap_or_else(|_| "127.0.0.1:8081".to_string());
+    let operator_secret: Option<Arc<str>> = std::env::var("OPERATOR_SECRET").ok().filter(|s| !s.is_empty()).map(Arc::from);
+    let operator_listener = TcpListener::bind(&operator_addr).await?;
+    if operator_secret.is_none() && !operator_listener.local_addr()?.ip().is_loopback() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("operator address {operator_addr} is not loopback: set OPERATOR_SECRET")));
+    }
+    println!("Operator commands accepted on {operator_addr}{}", if operator_secret.is_some() { " after auth" } else { "" });
+
+    let tx_operator = tx.clone();
+    let operator_handler_future = async move {
+        loop {
+            match operator_listener.accept().await {
+                Ok((stream, sockaddr)) => {
+                    println!("New operator connected from {sockaddr}");
+                    tokio::spawn(handle_operator(stream, sockaddr, tx_operator.clone(), operator_secret.clone()));
+                },
+                Err(e) => {
+                    eprintln!("Error accepting operator: {e}");
+                    break;
+                }
+            }
+        }
+    };
+
+    let logins = Logins::new();
+    let client_logins = logins.clone();
     let client_handler_future = async move {
         loop {
             match listener.accept().await {
                 Ok((stream, sockaddr)) => {
                     let tx_ob = tx.clone();
                     println!("New client connected from {sockaddr}");
-                    tokio::spawn(handle_client(stream, sockaddr, tx_ob));
+                    tokio::spawn(handle_client(stream, sockaddr, tx_ob, client_logins.clone()));
                 },
                 Err(e) => {
                     eprintln!("Error accepting connection: {e}");
@@ -122,41 +481,84 @@ async fn main() -> io::Result<()> {
         }
     };
 
-    let orderbook_handler_future = async move {
-        let mut orderbook = orderbook::orderbook::OrderBook::new();
-        loop {
-            if let Some(order) = rx.recv().await {
-                orderbook.handle_order(order, tx_price.clone(), c.clone());
-                println!("{orderbook}");
+    // Books trading on from a journal first replay it. The clients of the replayed
+    // requests got their reports the first time, so these go nowhere, and so do the
+    // cancels of the orders recovered. Reports on the recovered orders of a login go to
+    // its connection once it logs in again.
+    let (tx_recovered, rx_recovered) = mpsc::unbounded_channel::<ClientMessage>();
+    let mut registry = BookRegistry::new();
+    let mut publisher = MarketDataPublisher::new();
+    for (instrument, allocation) in instruments {
+        let mut orderbook = OrderBook::new(instrument.clone())
+            .with_volatility_auction(volatility_auction)
+            .with_halt_policy(halt_policy)
+            .with_allocation(allocation);
+        let path = Path::new(&journal_dir).join(format!("{}.journal", instrument.symbol()));
+        let (mut journal, contents) = Journal::open(&path, instrument.symbol(), &BookConfig::of(&orderbook), fsync)?;
+        if let Some(last) = contents.records().last() {
+            replay(&mut orderbook, contents.records(), &logins, &tx_recovered, &tx_market_data)?;
+            registry.skip_order_ids(contents.last_order_id());
+            for (owner, client_order_id) in contents.client_order_ids() {
+                registry.reserve_client_order_id(owner, client_order_id);
             }
+            println!("Recovered {} from {} journal records", instrument.symbol(), contents.records().len());
+            close_missed_session(&mut orderbook, &mut journal, *last.timestamp(), session_end, schedule.as_ref(), &tx_market_data)?;
+        }
+        // Recovered orders of logins stay live for their owners to reconnect to. The
+        // sessions of clients that did not log in ended with the restart, and nobody
+        // could be told about their fills or cancel them, so their orders are canceled.
+        if orderbook.anonymous_orders() > 0 {
+            println!("Canceling {} orders recovered in {} without a login", orderbook.anonymous_orders(), instrument.symbol());
+            let now = Utc::now();
+            journal.append(now, JournalEntry::CancelRecovered)?;
+            orderbook.cancel_anonymous(CancelReason::Recovery, now, &tx_market_data);
+        }
+        let tx_book = spawn_book(orderbook, journal, session_end, schedule.clone(), tx_market_data.clone(), c.clone())?;
+        publisher.register(instrument.symbol().clone());
+        registry.register(instrument, tx_book);
+    }
+    drop(rx_recovered);
+
+    let router_future = async move {
+        while let Some(request) = rx.recv().await {
+            registry.handle_request(request);
         }
     };
 
-    let _price_showcase_future = async move {
-        let mut stream = match TcpStream::connect("127.0.0.1:9000").await {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Unable to connect to price showcase server: {e}");
-                return;
+    let publisher_future = async move {
+        loop {
+            tokio::select! {
+                Some(update) = rx_market_data.recv() => publisher.publish(update),
+                Some(request) = rx_feed.recv() => publisher.handle_request(request),
+                else => break,
             }
-        };
+        }
+    };
+
+    // Market data subscribers connect here, e.g. MARKET_DATA_ADDR=127.0.0.1:9000.
+    let market_data_addr = std::env::var("MARKET_DATA_ADDR").unwrap_or_else(|_| "127.0.0.1:9000".to_string());
+    let market_data_listener = TcpListener::bind(&market_data_addr).await?;
+    println!("Market data feed listening on {market_data_addr}");
 
+    let subscriber_handler_future = async move {
         loop {
-            if let Some(price) = rx_price.recv().await {
-                if let Err(e) = stream.write_all(format!("{}\n", price).as_bytes()).await {
-                    eprintln!("Error sending price to server {}: {}", price, e);
+            match market_data_listener.accept().await {
+                Ok((stream, sockaddr)) => {
+                    println!("New market data subscriber from {sockaddr}");
+                    tokio::spawn(handle_subscriber(stream, sockaddr, tx_feed.clone()));
+                },
+                Err(e) => {
+                    eprintln!("Error accepting subscriber: {e}");
                     break;
                 }
-            } else {
-                break;
             }
         }
     };
 
-    tokio::spawn(orderbook_handler_future);
-
-    // tokio::spawn(price_showcase_future);
-    //
+    tokio::spawn(router_future);
+    tokio::spawn(publisher_future);
+    tokio::spawn(subscriber_handler_future);
+    tokio::spawn(operator_handler_future);
 
     tokio::select! {
         _ = client_handler_future => {},
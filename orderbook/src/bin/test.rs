@@ -5,21 +5,26 @@ use tokio::{
     time::{sleep, Duration},
 };
 
+const SYMBOLS: [&str; 3] = ["AAPL", "MSFT", "TSLA"];
+
 async fn client_loop(id: usize) {
+    let mut seq = 0;
     loop {
+        seq += 1;
         let (cmd, delay) = {
             let mut rng = rand::rng();
 
             let side = if rng.random_bool(0.5) { "buy" } else { "sell" };
             let order_type = if rng.random_bool(0.6) { "limit" } else { "market" };
+            let symbol = SYMBOLS[rng.random_range(0..SYMBOLS.len())];
 
             let command = if order_type == "limit" {
                 let price: usize = rng.random_range(70..=160);
                 let qty: usize = rng.random_range(1..=16);
-                format!("{side} limit {price} {qty}\n")
+                format!("{side} limit {symbol} {price} {qty} c{id}-{seq}\n")
             } else {
                 let qty: usize = rng.random_range(1..=100);
-                format!("{side} market {qty}\n")
+                format!("{side} market {symbol} {qty} c{id}-{seq}\n")
             };
 
             let delay_ms = rng.random_range(100..1500);
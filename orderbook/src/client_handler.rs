@@ -1,25 +1,226 @@
+use core::fmt;
 use tokio::sync::mpsc;
-use std::net::SocketAddr;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock},
+};
+
+use crate::{execution_report::ExecutionReport, market_data::{Depth, MarketData}};
+
+/// Everything the engine sends back to a connected client.
+#[derive(Debug)]
+pub enum ClientMessage {
+    Report(ExecutionReport),
+    Depth(Depth),
+    /// Phase change answering an operator's halt or resume.
+    MarketData(MarketData),
+}
+
+impl From<ExecutionReport> for ClientMessage {
+    fn from(report: ExecutionReport) -> Self {
+        ClientMessage::Report(report)
+    }
+}
+
+impl From<Depth> for ClientMessage {
+    fn from(depth: Depth) -> Self {
+        ClientMessage::Depth(depth)
+    }
+}
+
+impl From<MarketData> for ClientMessage {
+    fn from(update: MarketData) -> Self {
+        ClientMessage::MarketData(update)
+    }
+}
+
+/// Who a client's orders belong to: the name it logged in with, which outlives its
+/// connection and restarts of the server, or else the connection itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Owner {
+    Login(String),
+    Connection(SocketAddr),
+}
+
+impl Owner {
+    /// Owner logged in as `name`, which must be 1 to 32 letters, digits, `_`, `-` or `.`.
+    pub fn login(name: &str) -> Option<Self> {
+        let valid = (1..=MAX_LOGIN_LEN).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        valid.then(|| Owner::Login(name.to_string()))
+    }
+}
+
+const MAX_LOGIN_LEN: usize = 32;
+
+/// Logins are written with a leading `@`, connections as their address.
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::Login(name) => write!(f, "@{name}"),
+            Owner::Connection(sockaddr) => write!(f, "{sockaddr}"),
+        }
+    }
+}
+
+impl FromStr for Owner {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('@') {
+            Some(name) => Owner::login(name).ok_or(()),
+            None => s.parse().map(Owner::Connection).map_err(|_| ()),
+        }
+    }
+}
+
+/// Where the engine sends a client's messages. A login keeps the same mailbox across its
+/// connections, pointed at the latest one, so orders entered before a reconnect or a
+/// restart report to the connection their owner has now.
+pub type Mailbox = Arc<RwLock<mpsc::UnboundedSender<ClientMessage>>>;
 
 #[derive(Debug, Clone)]
 pub struct Client {
-    tx: mpsc::Sender<String>,
-    sockaddr: SocketAddr,
+    mailbox: Mailbox,
+    owner: Owner,
 }
 
 impl Client {
-    pub fn new(tx: mpsc::Sender<String>, sockaddr: SocketAddr) -> Self {
+    /// Client that has not logged in, so its orders belong to its connection.
+    pub fn new(tx: mpsc::UnboundedSender<ClientMessage>, sockaddr: SocketAddr) -> Self {
         Client {
-            tx,
-            sockaddr,
+            mailbox: Arc::new(RwLock::new(tx)),
+            owner: Owner::Connection(sockaddr),
+        }
+    }
+
+    pub fn tx(&self) -> mpsc::UnboundedSender<ClientMessage> {
+        self.mailbox.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    pub fn owner(&self) -> &Owner {
+        &self.owner
+    }
+}
+
+#[derive(Debug)]
+struct Login {
+    mailbox: Mailbox,
+    /// Connection logged in under the name, if any.
+    connection: Option<SocketAddr>,
+}
+
+/// Mailboxes of every name clients logged in with, shared by the connections and the
+/// journal replay. Only one connection at a time may be logged in under a name.
+#[derive(Debug, Clone, Default)]
+pub struct Logins {
+    logins: Arc<Mutex<HashMap<String, Login>>>,
+}
+
+impl Logins {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Login>> {
+        self.logins.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Logs the connection from `sockaddr` in as `name` and returns its client, whose
+    /// messages, and those of every order `name` entered before, now go to `tx`. Returns
+    /// `None` while another connection is logged in as `name`.
+    pub fn login(&self, name: &str, sockaddr: SocketAddr, tx: mpsc::UnboundedSender<ClientMessage>) -> Option<Client> {
+        let owner = Owner::login(name)?;
+        let mut logins = self.lock();
+        let login = logins.entry(name.to_string()).or_insert_with(|| Login {
+            mailbox: Arc::new(RwLock::new(tx.clone())),
+            connection: None,
+        });
+        if login.connection.is_some_and(|connection| connection != sockaddr) {
+            return None;
+        }
+        login.connection = Some(sockaddr);
+        *login.mailbox.write().unwrap_or_else(PoisonError::into_inner) = tx;
+        Some(Client { mailbox: login.mailbox.clone(), owner })
+    }
+
+    /// Ends the login of the connection from `sockaddr` as `name`, leaving the name free
+    /// for the next connection.
+    pub fn logout(&self, name: &str, sockaddr: SocketAddr) {
+        if let Some(login) = self.lock().get_mut(name).filter(|login| login.connection == Some(sockaddr)) {
+            login.connection = None;
         }
     }
 
-    pub fn tx(&self) -> mpsc::Sender<String> {
-        self.tx.clone()
+    /// Client of `owner` for a request replayed from a journal. Messages to a login go to
+    /// `tx` until the name logs in again, those to a connection always do.
+    pub fn recovered(&self, owner: &Owner, tx: &mpsc::UnboundedSender<ClientMessage>) -> Client {
+        let mailbox = match owner {
+            Owner::Login(name) => self.lock()
+                .entry(name.clone())
+                .or_insert_with(|| Login {
+                    mailbox: Arc::new(RwLock::new(tx.clone())),
+                    connection: None,
+                })
+                .mailbox
+                .clone(),
+            Owner::Connection(_) => Arc::new(RwLock::new(tx.clone())),
+        };
+        Client { mailbox, owner: owner.clone() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sockaddr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
     }
 
-    pub fn sockaddr(&self) -> SocketAddr {
-        self.sockaddr
+    #[test]
+    fn owners_round_trip() {
+        for owner in [Owner::Login("desk-1.a_b".to_string()), Owner::Connection(sockaddr(4000))] {
+            assert_eq!(owner.to_string().parse(), Ok(owner));
+        }
+        assert_eq!("@".parse::<Owner>(), Err(()));
+        assert_eq!("@a b".parse::<Owner>(), Err(()));
+        assert_eq!(Owner::login(&"x".repeat(33)), None);
+    }
+
+    fn depth() -> ClientMessage {
+        Depth::new("T".to_string(), 0, Vec::new(), Vec::new(), chrono::DateTime::UNIX_EPOCH).into()
+    }
+
+    #[test]
+    fn login_takes_over_recovered_mailbox() {
+        let logins = Logins::new();
+        let (tx_recovered, mut rx_recovered) = mpsc::unbounded_channel();
+        let recovered = logins.recovered(&Owner::Login("alice".to_string()), &tx_recovered);
+        recovered.tx().send(depth()).unwrap();
+        assert!(rx_recovered.try_recv().is_ok());
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let alice = logins.login("alice", sockaddr(1), tx).unwrap();
+        assert_eq!(alice.owner(), recovered.owner());
+        recovered.tx().send(depth()).unwrap();
+        assert!(rx.try_recv().is_ok());
+        assert!(rx_recovered.try_recv().is_err());
+    }
+
+    #[test]
+    fn one_connection_per_login() {
+        let logins = Logins::new();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        assert!(logins.login("alice", sockaddr(1), tx.clone()).is_some());
+        assert!(logins.login("alice", sockaddr(2), tx.clone()).is_none());
+        assert!(logins.login("bob", sockaddr(2), tx.clone()).is_some());
+
+        // Only the connection logged in can log out.
+        logins.logout("alice", sockaddr(2));
+        assert!(logins.login("alice", sockaddr(3), tx.clone()).is_none());
+        logins.logout("alice", sockaddr(1));
+        assert!(logins.login("alice", sockaddr(3), tx).is_some());
     }
 }
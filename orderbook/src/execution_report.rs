@@ -0,0 +1,191 @@
+use chrono::{DateTime, Utc};
+
+use crate::{price::Price, protocol::ParseError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    DuplicateClientOrderId,
+    UnknownOrder,
+    TooLateToAmend,
+    /// No book is registered for the order's symbol.
+    UnknownSymbol,
+    /// A price is not a multiple of the instrument's tick size.
+    PriceNotOnTick,
+    /// A price is outside the instrument's static price band.
+    PriceOutOfBand,
+    /// A quantity is not a multiple of the instrument's lot size.
+    QuantityNotOnLot,
+    /// The order quantity is below the instrument's minimum or above its maximum.
+    QuantityOutOfRange,
+    /// Post-only order that would have taken liquidity on arrival.
+    PostOnly,
+    /// The order type or time in force is not accepted in the book's current trading
+    /// phase, e.g. a market order during an auction call or any new order once closed or
+    /// halted. Also refuses halts and resumes that do not apply to the current phase.
+    TradingPhase,
+    Malformed(ParseError),
+}
+
+impl RejectReason {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectReason::DuplicateClientOrderId => "DUPLICATE_CLORDID",
+            RejectReason::UnknownOrder => "UNKNOWN_ORDER",
+            RejectReason::TooLateToAmend => "TOO_LATE_TO_AMEND",
+            RejectReason::UnknownSymbol => "UNKNOWN_SYMBOL",
+            RejectReason::PriceNotOnTick => "PRICE_NOT_ON_TICK",
+            RejectReason::PriceOutOfBand => "PRICE_OUT_OF_BAND",
+            RejectReason::QuantityNotOnLot => "QTY_NOT_ON_LOT",
+            RejectReason::QuantityOutOfRange => "QTY_OUT_OF_RANGE",
+            RejectReason::PostOnly => "POST_ONLY",
+            RejectReason::TradingPhase => "TRADING_PHASE",
+            RejectReason::Malformed(e) => e.code(),
+        }
+    }
+}
+
+/// Why an order, or what was left of it, stopped working in the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    /// The owner sent a cancel.
+    Requested,
+    /// IOC remainder that could not fill on arrival.
+    ImmediateOrCancel,
+    /// FOK order that could not fill completely on arrival.
+    FillOrKill,
+    /// Market order remainder with nothing left on the opposite side.
+    NoLiquidity,
+    /// Market order remainder beyond its protection price.
+    PriceProtection,
+    /// Post-only order that would have taken liquidity when it entered the book after
+    /// being acknowledged, e.g. a triggered stop-limit or an amended order, or that
+    /// could only be repriced off the tick or out of the price band.
+    PostOnly,
+    /// Self-trade prevention stopped the order from trading with its own account.
+    SelfTrade,
+    /// The order would have traded outside the volatility band, which started a volatility
+    /// auction, and it cannot rest until the uncross.
+    VolatilityAuction,
+    /// Order held during a halt that the phase trading resumed in does not accept.
+    TradingPhase,
+    /// Order recovered from the journal after a restart, entered by a client that did not
+    /// log in, whose session the restart ended.
+    Recovery,
+}
+
+impl CancelReason {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CancelReason::Requested => "REQUESTED",
+            CancelReason::ImmediateOrCancel => "IOC",
+            CancelReason::FillOrKill => "FOK",
+            CancelReason::NoLiquidity => "NO_LIQUIDITY",
+            CancelReason::PriceProtection => "PRICE_PROTECTION",
+            CancelReason::PostOnly => "POST_ONLY",
+            CancelReason::SelfTrade => "SELF_TRADE",
+            CancelReason::VolatilityAuction => "VOLATILITY_AUCTION",
+            CancelReason::TradingPhase => "TRADING_PHASE",
+            CancelReason::Recovery => "RECOVERY",
+        }
+    }
+}
+
+/// Fields shared by every execution report. `leaves_qty` is the quantity still working in
+/// the book and drops to zero once the order is done (filled, canceled or expired).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionDetails {
+    exec_id: u64,
+    order_id: u64,
+    client_order_id: String,
+    symbol: String,
+    last_qty: usize,
+    last_price: Price,
+    cum_qty: usize,
+    leaves_qty: usize,
+    timestamp: DateTime<Utc>,
+}
+
+impl ExecutionDetails {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(exec_id: u64, order_id: u64, client_order_id: String, symbol: String, last_qty: usize, last_price: Price, cum_qty: usize, leaves_qty: usize, timestamp: DateTime<Utc>) -> Self {
+        ExecutionDetails {
+            exec_id,
+            order_id,
+            client_order_id,
+            symbol,
+            last_qty,
+            last_price,
+            cum_qty,
+            leaves_qty,
+            timestamp,
+        }
+    }
+
+    /// Sequence number of the report within its book, unique across books together with
+    /// the symbol.
+    pub fn exec_id(&self) -> u64 {
+        self.exec_id
+    }
+
+    pub fn order_id(&self) -> u64 {
+        self.order_id
+    }
+
+    pub fn client_order_id(&self) -> &String {
+        &self.client_order_id
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn last_qty(&self) -> usize {
+        self.last_qty
+    }
+
+    pub fn last_price(&self) -> Price {
+        self.last_price
+    }
+
+    pub fn cum_qty(&self) -> usize {
+        self.cum_qty
+    }
+
+    pub fn leaves_qty(&self) -> usize {
+        self.leaves_qty
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+}
+
+/// Report sent by the `OrderBook` to the owner of an order whenever its state changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionReport {
+    New(ExecutionDetails),
+    PartialFill(ExecutionDetails),
+    Fill(ExecutionDetails),
+    Replaced(ExecutionDetails),
+    /// The book reduced the working quantity on its own, e.g. decrement-and-cancel
+    /// self-trade prevention.
+    Restated(ExecutionDetails),
+    Canceled(ExecutionDetails, CancelReason),
+    Rejected(ExecutionDetails, RejectReason),
+    Expired(ExecutionDetails),
+}
+
+impl ExecutionReport {
+    pub fn details(&self) -> &ExecutionDetails {
+        match self {
+            ExecutionReport::New(details)
+            | ExecutionReport::PartialFill(details)
+            | ExecutionReport::Fill(details)
+            | ExecutionReport::Replaced(details)
+            | ExecutionReport::Restated(details)
+            | ExecutionReport::Canceled(details, _)
+            | ExecutionReport::Rejected(details, _)
+            | ExecutionReport::Expired(details) => details,
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::{fs, io};
+
+use crate::{
+    allocation::{parse_allocation, AllocationPolicy, Fifo},
+    execution_report::RejectReason,
+    orders::{Order, Orders},
+    price::Price,
+    protocol::{parse_price, parse_symbol},
+};
+
+/// Reference data of a traded instrument: the price and size grid orders must respect,
+/// a static band around a reference price outside which prices are refused, and a
+/// dynamic band around the last traded price outside which trades may not print. The
+/// number of decimals of the tick size is the price scale of the instrument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instrument {
+    symbol: String,
+    tick_size: Price,
+    lot_size: usize,
+    min_qty: usize,
+    max_qty: usize,
+    reference_price: Price,
+    /// Half-width of the price band around `reference_price`, in basis points.
+    price_band_bps: usize,
+    /// Half-width of the volatility band around the last traded price, in basis points.
+    /// Zero turns the circuit breaker off.
+    volatility_band_bps: usize,
+}
+
+impl Instrument {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(symbol: String, tick_size: Price, lot_size: usize, min_qty: usize, max_qty: usize, reference_price: Price, price_band_bps: usize, volatility_band_bps: usize) -> Self {
+        Instrument {
+            symbol,
+            tick_size,
+            lot_size,
+            min_qty,
+            max_qty,
+            reference_price,
+            price_band_bps,
+            volatility_band_bps,
+        }
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn tick_size(&self) -> Price {
+        self.tick_size
+    }
+
+    /// Decimals every price of the instrument is written with.
+    pub fn price_scale(&self) -> u32 {
+        self.tick_size.scale()
+    }
+
+    pub fn lot_size(&self) -> usize {
+        self.lot_size
+    }
+
+    pub fn min_qty(&self) -> usize {
+        self.min_qty
+    }
+
+    pub fn max_qty(&self) -> usize {
+        self.max_qty
+    }
+
+    pub fn reference_price(&self) -> Price {
+        self.reference_price
+    }
+
+    pub fn price_band_bps(&self) -> usize {
+        self.price_band_bps
+    }
+
+    /// Lowest and highest price accepted, both inclusive.
+    pub fn price_band(&self) -> (Price, Price) {
+        let band = self.reference_price.basis_points(self.price_band_bps);
+        let low = self.reference_price.checked_sub(band).unwrap_or_default();
+        let high = self.reference_price.checked_add(band).unwrap_or(Price::new(u64::MAX, self.reference_price.scale()));
+        (low, high)
+    }
+
+    pub fn volatility_band_bps(&self) -> usize {
+        self.volatility_band_bps
+    }
+
+    /// Lowest and highest price a trade may print at in continuous trading, both
+    /// inclusive, given the last traded price. `None` if there is no volatility band.
+    pub fn volatility_band(&self, last_price: Price) -> Option<(Price, Price)> {
+        if self.volatility_band_bps == 0 {
+            return None;
+        }
+        let band = last_price.basis_points(self.volatility_band_bps);
+        let low = last_price.checked_sub(band).unwrap_or_default();
+        let high = last_price.checked_add(band).unwrap_or(Price::new(u64::MAX, last_price.scale()));
+        Some((low, high))
+    }
+
+    /// Checks a price and returns it written at the instrument's scale.
+    pub fn check_price(&self, price: Price) -> Result<Price, RejectReason> {
+        let price = price.rescale(self.price_scale()).ok_or(RejectReason::PriceNotOnTick)?;
+        if !price.is_multiple_of(self.tick_size) {
+            return Err(RejectReason::PriceNotOnTick);
+        }
+        let (low, high) = self.price_band();
+        if price < low || price > high {
+            return Err(RejectReason::PriceOutOfBand);
+        }
+        Ok(price)
+    }
+
+    pub fn check_qty(&self, qty: usize) -> Result<(), RejectReason> {
+        if !qty.is_multiple_of(self.lot_size) {
+            return Err(RejectReason::QuantityNotOnLot);
+        }
+        if qty < self.min_qty || qty > self.max_qty {
+            return Err(RejectReason::QuantityOutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Checks every price and size of a request against the reference data and rewrites
+    /// its prices at the instrument's scale. Cancels, depth requests and operator requests
+    /// carry neither and always pass.
+    pub fn validate(&self, order: &mut Orders) -> Result<(), RejectReason> {
+        match order {
+            Orders::Market(market_order) => self.check_qty(market_order.size()),
+            Orders::Limit(limit_order) => {
+                self.check_qty(limit_order.size())?;
+                self.check_display_size(limit_order.display_size())?;
+                limit_order.set_price(self.check_price(limit_order.price())?);
+                Ok(())
+            },
+            Orders::Cancel(_) | Orders::Depth(_) | Orders::Control(_) => Ok(()),
+            Orders::Amend(amend_order) => {
+                self.check_qty(amend_order.size())?;
+                amend_order.set_price(self.check_price(amend_order.price())?);
+                Ok(())
+            },
+            Orders::Stop(stop_order) => {
+                self.check_qty(stop_order.order().size())?;
+                stop_order.set_trigger_price(self.check_price(stop_order.trigger_price())?);
+                Ok(())
+            },
+            Orders::StopLimit(stop_limit_order) => {
+                self.check_qty(stop_limit_order.order().size())?;
+                self.check_display_size(stop_limit_order.order().display_size())?;
+                stop_limit_order.set_trigger_price(self.check_price(stop_limit_order.trigger_price())?);
+                let price = self.check_price(stop_limit_order.order().price())?;
+                stop_limit_order.order_mut().set_price(price);
+                Ok(())
+            },
+        }
+    }
+
+    /// Iceberg slices must be whole lots too, but may be smaller than the minimum quantity.
+    fn check_display_size(&self, display_size: Option<usize>) -> Result<(), RejectReason> {
+        match display_size {
+            Some(display_size) if !display_size.is_multiple_of(self.lot_size) => Err(RejectReason::QuantityNotOnLot),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Reads instrument reference data from a CSV file with a header line and the columns
+/// `symbol,tick_size,lot_size,min_qty,max_qty,reference_price,price_band_bps`, then
+/// optionally `volatility_band_bps` (no circuit breaker if left out) and `allocation`
+/// (FIFO if left out). Prices are decimals, and the decimals of the tick size set the
+/// price scale of the instrument.
+pub fn load_instruments(path: &str) -> io::Result<Vec<(Instrument, Box<dyn AllocationPolicy>)>> {
+    let invalid = |line: usize, msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{path}:{line}: {msg}"));
+
+    let mut instruments = Vec::new();
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate().skip(1) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if !(7..=9).contains(&fields.len()) {
+            return Err(invalid(i + 1, "expected 7 to 9 fields"));
+        }
+        let symbol = parse_symbol(fields[0]).map_err(|e| invalid(i + 1, &e.to_string()))?;
+        let price = |field: &str| parse_price(field).map_err(|e| invalid(i + 1, &e.to_string()));
+        let integer = |field: &str| field.parse::<usize>().map_err(|_| invalid(i + 1, "expected a non-negative integer"));
+
+        // A zero tick would put every price on the grid and break the price scale.
+        let tick_size = parse_price(fields[1]).map_err(|_| invalid(i + 1, "tick size must be a positive decimal"))?;
+        let [lot_size, min_qty, max_qty] = [integer(fields[2])?, integer(fields[3])?, integer(fields[4])?];
+        let reference_price = price(fields[5])?
+            .rescale(tick_size.scale())
+            .ok_or_else(|| invalid(i + 1, "reference price has more decimals than the tick size"))?;
+        let price_band_bps = integer(fields[6])?;
+        let volatility_band_bps = fields.get(7).map_or(Ok(0), |field| integer(field))?;
+        if lot_size == 0 || min_qty > max_qty {
+            return Err(invalid(i + 1, "lot size must be positive and min_qty at most max_qty"));
+        }
+        let allocation = match fields.get(8) {
+            Some(field) => parse_allocation(field).ok_or_else(|| invalid(i + 1, "allocation must be fifo or prorata[/top][/min=<lots>]"))?,
+            None => Box::new(Fifo),
+        };
+        let instrument = Instrument::new(symbol, tick_size, lot_size, min_qty, max_qty, reference_price, price_band_bps, volatility_band_bps);
+        instruments.push((instrument, allocation));
+    }
+    Ok(instruments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, rows: &str) -> io::Result<Vec<(Instrument, Box<dyn AllocationPolicy>)>> {
+        let path = std::env::temp_dir().join(format!("instruments-{name}-{}.csv", std::process::id()));
+        fs::write(&path, format!("symbol,tick_size,lot_size,min_qty,max_qty,reference_price,price_band_bps\n{rows}\n"))?;
+        let result = load_instruments(path.to_str().unwrap());
+        fs::remove_file(&path)?;
+        result
+    }
+
+    #[test]
+    fn zero_tick_size_rejected() {
+        for tick_size in ["0", "0.00"] {
+            let err = load("zero-tick", &format!("T,{tick_size},1,1,1000,100,5000")).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().ends_with(":2: tick size must be a positive decimal"), "{err}");
+        }
+        assert_eq!(load("tick", "T,0.01,1,1,1000,100,5000").unwrap().len(), 1);
+    }
+}
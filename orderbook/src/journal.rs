@@ -0,0 +1,776 @@
+use core::fmt;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::{atomic::AtomicU64, Arc},
+};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::mpsc;
+
+use crate::{
+    client_handler::{ClientMessage, Logins, Owner},
+    execution_report::CancelReason,
+    market_data::MarketData,
+    orderbook::OrderBook,
+    orders::Orders,
+    protocol,
+    session::TradingPhase,
+};
+
+/// Version written in the header of new journals and the only one this build reads.
+pub const JOURNAL_VERSION: u32 = 2;
+
+const MAGIC: &str = "ORDERBOOK-JOURNAL";
+
+/// Settings that decide what a book does with its requests: the reference data of the
+/// instrument, the allocation policy, the halt policy and the length of volatility
+/// auctions. A journal records them in its header, since it only replays to the same
+/// book under the same settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookConfig {
+    settings: Vec<(String, String)>,
+}
+
+impl BookConfig {
+    pub fn of(orderbook: &OrderBook) -> Self {
+        let instrument = orderbook.instrument();
+        let settings = [
+            ("tick_size", instrument.tick_size().to_string()),
+            ("lot_size", instrument.lot_size().to_string()),
+            ("min_qty", instrument.min_qty().to_string()),
+            ("max_qty", instrument.max_qty().to_string()),
+            ("reference_price", instrument.reference_price().to_string()),
+            ("price_band_bps", instrument.price_band_bps().to_string()),
+            ("volatility_band_bps", instrument.volatility_band_bps().to_string()),
+            ("allocation", orderbook.allocation().to_string()),
+            ("halt_orders", orderbook.halt_policy().code().to_string()),
+            ("volatility_auction_secs", orderbook.volatility_auction().num_seconds().to_string()),
+        ];
+        BookConfig {
+            settings: settings.into_iter().map(|(name, value)| (name.to_string(), value)).collect(),
+        }
+    }
+
+    /// Reads the `name=value` pairs written by `Display`.
+    fn parse(s: &str) -> Option<Self> {
+        let settings = s
+            .split_whitespace()
+            .map(|setting| setting.split_once('=').map(|(name, value)| (name.to_string(), value.to_string())))
+            .collect::<Option<Vec<_>>>()?;
+        Some(BookConfig { settings })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.settings.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Every setting that `other` has another value for (or lacks), as
+    /// `name=<this value> (now <other value>)`.
+    pub fn differences(&self, other: &BookConfig) -> Vec<String> {
+        let added = other.settings.iter().filter(|(name, _)| self.get(name).is_none());
+        self.settings
+            .iter()
+            .chain(added)
+            .map(|(name, _)| name.as_str())
+            .filter(|name| self.get(name) != other.get(name))
+            .map(|name| format!("{name}={} (now {})", self.get(name).unwrap_or("-"), other.get(name).unwrap_or("-")))
+            .collect()
+    }
+}
+
+impl fmt::Display for BookConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let settings: Vec<String> = self.settings.iter().map(|(name, value)| format!("{name}={value}")).collect();
+        write!(f, "{}", settings.join(" "))
+    }
+}
+
+/// Something a book acted on, in the order it acted on it. Replaying the entries of a
+/// journal against an empty book rebuilds the book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalEntry {
+    /// A request as it reached the book: the client that owns it (its login, or the
+    /// address of a client that did not log in), the order id the registry assigned (zero
+    /// if it does not enter an order) and the request written as a protocol line.
+    Request(Owner, u64, String),
+    /// A scheduled change of trading phase.
+    Phase(TradingPhase),
+    /// The end of a volatility auction.
+    EndVolatilityAuction,
+    /// The end of the trading session, when DAY orders expire.
+    ExpireDayOrders,
+    /// A restart, which canceled the recovered orders of clients that did not log in.
+    CancelRecovered,
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalEntry::Request(owner, order_id, line) => write!(f, "request {owner} {order_id} {line}"),
+            JournalEntry::Phase(phase) => write!(f, "phase {}", phase.code()),
+            JournalEntry::EndVolatilityAuction => write!(f, "end_volatility_auction"),
+            JournalEntry::ExpireDayOrders => write!(f, "expire_day_orders"),
+            JournalEntry::CancelRecovered => write!(f, "cancel_recovered"),
+        }
+    }
+}
+
+/// One line of a journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalRecord {
+    seq: u64,
+    timestamp: DateTime<Utc>,
+    entry: JournalEntry,
+}
+
+impl JournalRecord {
+    pub fn new(seq: u64, timestamp: DateTime<Utc>, entry: JournalEntry) -> Self {
+        JournalRecord {
+            seq,
+            timestamp,
+            entry,
+        }
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+
+    pub fn entry(&self) -> &JournalEntry {
+        &self.entry
+    }
+}
+
+/// When appended records are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    /// After every record, so nothing the book acted on can be lost.
+    #[default]
+    Always,
+    /// After this many records; a crash loses at most the records since the last sync.
+    Every(usize),
+    /// Never, leaving it to the operating system.
+    Never,
+}
+
+/// Reasons a journal cannot be opened or read.
+#[derive(Debug)]
+pub enum JournalError {
+    Io(io::Error),
+    /// The file is not a journal, or it was written by a version this build cannot read.
+    UnsupportedVersion(String),
+    /// A complete record that fails its checksum or cannot be parsed, by line number.
+    Corrupt { line: usize, reason: &'static str },
+    /// The journal belongs to another symbol than the book that opens it.
+    WrongSymbol(String),
+    /// The journal was written by a book with other settings, listed as by
+    /// `BookConfig::differences`.
+    ConfigMismatch(Vec<String>),
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JournalError::Io(e) => write!(f, "{e}"),
+            JournalError::UnsupportedVersion(header) => write!(f, "unsupported journal header {header:?}, expected {MAGIC} v{JOURNAL_VERSION}"),
+            JournalError::Corrupt { line, reason } => write!(f, "corrupt record on line {line}: {reason}"),
+            JournalError::WrongSymbol(symbol) => write!(f, "journal belongs to {symbol}"),
+            JournalError::ConfigMismatch(differences) => write!(f, "journal was written with other settings: {}", differences.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+impl From<io::Error> for JournalError {
+    fn from(e: io::Error) -> Self {
+        JournalError::Io(e)
+    }
+}
+
+impl From<JournalError> for io::Error {
+    fn from(e: JournalError) -> Self {
+        match e {
+            JournalError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
+
+/// Contents of a journal file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalContents {
+    symbol: String,
+    config: BookConfig,
+    records: Vec<JournalRecord>,
+    /// Length of the file up to the last complete record.
+    valid_len: usize,
+}
+
+impl JournalContents {
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    /// Settings of the book that wrote the journal.
+    pub fn config(&self) -> &BookConfig {
+        &self.config
+    }
+
+    /// Fails unless the journal was written by a book with `config`, so that replaying
+    /// it gives the same book again.
+    pub fn check_config(&self, config: &BookConfig) -> Result<(), JournalError> {
+        let differences = self.config.differences(config);
+        if differences.is_empty() {
+            Ok(())
+        } else {
+            Err(JournalError::ConfigMismatch(differences))
+        }
+    }
+
+    pub fn records(&self) -> &[JournalRecord] {
+        &self.records
+    }
+
+    pub fn into_records(self) -> Vec<JournalRecord> {
+        self.records
+    }
+
+    /// Highest order id the registry had assigned to a request in the journal.
+    pub fn last_order_id(&self) -> u64 {
+        self.records
+            .iter()
+            .filter_map(|record| match record.entry {
+                JournalEntry::Request(_, order_id, _) => Some(order_id),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Client order ids of the orders entered by logins, whose sessions go on after the
+    /// restart.
+    pub fn client_order_ids(&self) -> Vec<(Owner, String)> {
+        let (tx, _) = mpsc::unbounded_channel();
+        self.records
+            .iter()
+            .filter_map(|record| match &record.entry {
+                JournalEntry::Request(owner @ Owner::Login(_), _, line) => {
+                    let request = protocol::parse_book_request(line, Logins::new().recovered(owner, &tx), record.timestamp).ok()?;
+                    request.new_order().map(|order| (owner.clone(), order.client_order_id().clone()))
+                },
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Write-ahead journal of one book. Every record is a line
+/// `<crc32> <seq> <timestamp> <entry>`, where the checksum covers the rest of the line,
+/// after a header line naming the format version, the symbol and the book's settings.
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    seq: u64,
+    fsync: FsyncPolicy,
+    unsynced: usize,
+}
+
+impl Journal {
+    /// Opens the journal of `symbol` at `path` for appending, creating it if it does not
+    /// exist, and returns it with what it already holds. The records already in it are
+    /// checked first, and so is the book's `config` against the one they were written
+    /// with. A last record without its line end was cut off by a crash while being
+    /// written; the book never acted on it, so it is dropped.
+    pub fn open(path: &Path, symbol: &str, config: &BookConfig, fsync: FsyncPolicy) -> Result<(Self, JournalContents), JournalError> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let contents = if file.metadata()?.len() == 0 {
+            let header = format!("{MAGIC} v{JOURNAL_VERSION} {symbol} {config}\n");
+            file.write_all(header.as_bytes())?;
+            file.sync_all()?;
+            JournalContents {
+                symbol: symbol.to_string(),
+                config: config.clone(),
+                records: Vec::new(),
+                valid_len: header.len(),
+            }
+        } else {
+            let contents = read_journal(path)?;
+            if contents.symbol != symbol {
+                return Err(JournalError::WrongSymbol(contents.symbol));
+            }
+            contents.check_config(config)?;
+            if (contents.valid_len as u64) < file.metadata()?.len() {
+                eprintln!("Dropping incomplete last record of {}", path.display());
+                file.set_len(contents.valid_len as u64)?;
+                file.sync_all()?;
+            }
+            contents
+        };
+
+        let journal = Journal {
+            file,
+            seq: contents.records.last().map(|record| record.seq).unwrap_or(0),
+            fsync,
+            unsynced: 0,
+        };
+        Ok((journal, contents))
+    }
+
+    /// Sequence number of the last record written.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Appends an entry under the next sequence number and returns that number. The
+    /// record is on disk when this returns if the fsync policy says so.
+    pub fn append(&mut self, timestamp: DateTime<Utc>, entry: JournalEntry) -> io::Result<u64> {
+        let seq = self.seq + 1;
+        let body = format!("{seq} {} {entry}", timestamp.to_rfc3339());
+        self.file.write_all(format!("{:08x} {body}\n", crc32(body.as_bytes())).as_bytes())?;
+        self.seq = seq;
+
+        self.unsynced += 1;
+        let sync = match self.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Every(records) => self.unsynced >= records,
+            FsyncPolicy::Never => false,
+        };
+        if sync {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+        Ok(seq)
+    }
+
+    /// Journals a request before the book handles it. Depth requests change nothing in
+    /// the book and are not journaled.
+    pub fn append_request(&mut self, request: &Orders) -> io::Result<()> {
+        let Some(line) = protocol::format_request(request) else {
+            return Ok(());
+        };
+        let order_id = request.new_order().map(|order| order.order_id()).unwrap_or(0);
+        self.append(*request.timestamp(), JournalEntry::Request(request.client().owner().clone(), order_id, line))?;
+        Ok(())
+    }
+}
+
+/// Reads and checks a whole journal. A last record without its line end is left out, as
+/// `Journal::open` would drop it.
+pub fn read_journal(path: &Path) -> Result<JournalContents, JournalError> {
+    let data = fs::read(path)?;
+    let text = String::from_utf8_lossy(&data);
+
+    let mut lines = text.split_inclusive('\n');
+    let header = lines.next().unwrap_or_default();
+    let (symbol, config) = match header.strip_suffix('\n').unwrap_or(header).splitn(4, ' ').collect::<Vec<_>>()[..] {
+        [MAGIC, version, symbol, config] if version == format!("v{JOURNAL_VERSION}") => match BookConfig::parse(config) {
+            Some(config) => (symbol.to_string(), config),
+            None => return Err(JournalError::Corrupt { line: 1, reason: "invalid book settings" }),
+        },
+        _ => return Err(JournalError::UnsupportedVersion(header.trim_end().to_string())),
+    };
+
+    let mut valid_len = header.len();
+    let mut records = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let Some(line) = line.strip_suffix('\n') else {
+            break;
+        };
+        let record = parse_record(line).map_err(|reason| JournalError::Corrupt { line: index + 2, reason })?;
+        if record.seq != records.last().map(|last: &JournalRecord| last.seq).unwrap_or(0) + 1 {
+            return Err(JournalError::Corrupt { line: index + 2, reason: "sequence gap" });
+        }
+        records.push(record);
+        valid_len += line.len() + 1;
+    }
+
+    Ok(JournalContents {
+        symbol,
+        config,
+        records,
+        valid_len,
+    })
+}
+
+/// Feeds journaled records to a book the way they were fed the first time: requests are
+/// parsed back from their lines with the timestamp, owner and order id of the record,
+/// and timer events happen at the time they did. The book takes every timestamp and id
+/// from its inputs, so it ends up exactly as it was after the last record.
+/// Recovered orders of a login report to that login in `logins`, to `tx_reports` until
+/// it logs in again; other execution reports go to `tx_reports`, and market data to
+/// `tx_market_data`.
+pub fn replay(orderbook: &mut OrderBook, records: &[JournalRecord], logins: &Logins, tx_reports: &mpsc::UnboundedSender<ClientMessage>, tx_market_data: &mpsc::UnboundedSender<MarketData>) -> Result<(), JournalError> {
+    let counter = Arc::new(AtomicU64::new(0));
+    for record in records {
+        let timestamp = record.timestamp;
+        match &record.entry {
+            JournalEntry::Request(owner, order_id, line) => {
+                let client = logins.recovered(owner, tx_reports);
+                let mut request = protocol::parse_book_request(line, client, timestamp)
+                    .map_err(|_| JournalError::Corrupt { line: record.seq as usize + 1, reason: "request does not parse" })?;
+                if let Some(new_order) = request.new_order_mut() {
+                    new_order.set_order_id(*order_id);
+                }
+                orderbook.handle_order(request, tx_market_data.clone(), counter.clone());
+            },
+            JournalEntry::Phase(phase) => orderbook.set_phase(*phase, timestamp, tx_market_data),
+            JournalEntry::EndVolatilityAuction => orderbook.end_volatility_auction(timestamp, tx_market_data),
+            JournalEntry::ExpireDayOrders => orderbook.expire_day_orders(timestamp, tx_market_data),
+            JournalEntry::CancelRecovered => orderbook.cancel_anonymous(CancelReason::Recovery, timestamp, tx_market_data),
+        }
+    }
+    Ok(())
+}
+
+fn parse_record(line: &str) -> Result<JournalRecord, &'static str> {
+    let (checksum, body) = line.split_once(' ').ok_or("missing checksum")?;
+    let checksum = u32::from_str_radix(checksum, 16).map_err(|_| "invalid checksum")?;
+    if checksum != crc32(body.as_bytes()) {
+        return Err("checksum mismatch");
+    }
+
+    let mut fields = body.splitn(4, ' ');
+    let seq = fields.next().and_then(|seq| seq.parse().ok()).ok_or("invalid sequence number")?;
+    let timestamp = fields
+        .next()
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .ok_or("invalid timestamp")?
+        .with_timezone(&Utc);
+    let entry = match (fields.next(), fields.next()) {
+        (Some("request"), Some(request)) => {
+            let mut fields = request.splitn(3, ' ');
+            let owner = fields.next().and_then(|owner| owner.parse().ok()).ok_or("invalid owner")?;
+            let order_id = fields.next().and_then(|order_id| order_id.parse().ok()).ok_or("invalid order id")?;
+            let line = fields.next().ok_or("missing request")?;
+            JournalEntry::Request(owner, order_id, line.to_string())
+        },
+        (Some("phase"), Some(code)) => JournalEntry::Phase(TradingPhase::from_code(code).ok_or("unknown trading phase")?),
+        (Some("end_volatility_auction"), None) => JournalEntry::EndVolatilityAuction,
+        (Some("expire_day_orders"), None) => JournalEntry::ExpireDayOrders,
+        (Some("cancel_recovered"), None) => JournalEntry::CancelRecovered,
+        _ => return Err("unknown entry"),
+    };
+
+    Ok(JournalRecord::new(seq, timestamp, entry))
+}
+
+/// CRC-32 (IEEE 802.3) lookup table.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{execution_report::{ExecutionReport, RejectReason}, instrument::Instrument, orders::Order};
+
+    /// Fresh path in the temp dir; `name` keeps tests running in parallel apart.
+    fn path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("orderbook-{name}-{}.journal", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn config() -> BookConfig {
+        let instrument = Instrument::new("T".to_string(), "0.01".parse().unwrap(), 1, 1, 1000, "100.00".parse().unwrap(), 5000, 0);
+        BookConfig::of(&OrderBook::new(instrument))
+    }
+
+    fn header(symbol: &str) -> String {
+        format!("{MAGIC} v{JOURNAL_VERSION} {symbol} {}\n", config())
+    }
+
+    fn record(seq: u64, entry: &str) -> String {
+        let body = format!("{seq} 2026-01-02T09:00:00+00:00 {entry}");
+        format!("{:08x} {body}\n", crc32(body.as_bytes()))
+    }
+
+    fn write(name: &str, lines: &[String]) -> std::path::PathBuf {
+        let path = path(name);
+        fs::write(&path, lines.concat()).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_back_what_was_appended() {
+        let path = path("append");
+        let (mut journal, contents) = Journal::open(&path, "T", &config(), FsyncPolicy::Always).unwrap();
+        assert!(contents.records().is_empty());
+        let timestamp = DateTime::parse_from_rfc3339("2026-01-02T09:00:00Z").unwrap().with_timezone(&Utc);
+        let owner = Owner::Connection(([127, 0, 0, 1], 4000).into());
+        let login = Owner::login("desk-1").unwrap();
+        journal.append(timestamp, JournalEntry::Request(owner.clone(), 7, "buy limit T 100.00 5 a gtc".to_string())).unwrap();
+        journal.append(timestamp, JournalEntry::Request(login.clone(), 8, "sell limit T 101.00 5 b gtc".to_string())).unwrap();
+        journal.append(timestamp, JournalEntry::Phase(TradingPhase::Halted)).unwrap();
+        journal.append(timestamp, JournalEntry::ExpireDayOrders).unwrap();
+        drop(journal);
+
+        let contents = read_journal(&path).unwrap();
+        assert_eq!(contents.symbol(), "T");
+        assert_eq!(contents.config(), &config());
+        assert_eq!(contents.last_order_id(), 8);
+        let entries: Vec<&JournalEntry> = contents.records().iter().map(JournalRecord::entry).collect();
+        assert_eq!(entries, [&JournalEntry::Request(owner, 7, "buy limit T 100.00 5 a gtc".to_string()), &JournalEntry::Request(login, 8, "sell limit T 101.00 5 b gtc".to_string()), &JournalEntry::Phase(TradingPhase::Halted), &JournalEntry::ExpireDayOrders]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn checksum_mismatch_is_corrupt() {
+        let tampered = record(2, "expire_day_orders").replacen("expire_day_orders", "end_volatility_auction", 1);
+        let path = write("crc", &[header("T"), record(1, "phase CONTINUOUS"), tampered]);
+        assert!(matches!(read_journal(&path), Err(JournalError::Corrupt { line: 3, reason: "checksum mismatch" })));
+        assert!(matches!(Journal::open(&path, "T", &config(), FsyncPolicy::Always), Err(JournalError::Corrupt { line: 3, .. })));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn sequence_gap_is_corrupt() {
+        let path = write("gap", &[header("T"), record(1, "phase CONTINUOUS"), record(3, "expire_day_orders")]);
+        assert!(matches!(read_journal(&path), Err(JournalError::Corrupt { line: 3, reason: "sequence gap" })));
+        fs::remove_file(&path).unwrap();
+
+        let path = write("gap-start", &[header("T"), record(2, "phase CONTINUOUS")]);
+        assert!(matches!(read_journal(&path), Err(JournalError::Corrupt { line: 2, reason: "sequence gap" })));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn torn_tail_is_dropped() {
+        let torn = record(2, "expire_day_orders");
+        let path = write("torn", &[header("T"), record(1, "phase CONTINUOUS"), torn[..torn.len() / 2].to_string()]);
+        assert_eq!(read_journal(&path).unwrap().records().len(), 1);
+
+        let (mut journal, contents) = Journal::open(&path, "T", &config(), FsyncPolicy::Always).unwrap();
+        assert_eq!(contents.records().len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), header("T") + &record(1, "phase CONTINUOUS"));
+        assert_eq!(journal.append(Utc::now(), JournalEntry::EndVolatilityAuction).unwrap(), 2);
+        drop(journal);
+
+        let contents = read_journal(&path).unwrap();
+        assert_eq!(contents.records().last().map(|record| (record.seq(), record.entry().clone())), Some((2, JournalEntry::EndVolatilityAuction)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wrong_symbol_refused() {
+        let path = write("symbol", &[header("U"), record(1, "phase CONTINUOUS")]);
+        assert!(matches!(Journal::open(&path, "T", &config(), FsyncPolicy::Always), Err(JournalError::WrongSymbol(symbol)) if symbol == "U"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wrong_version_refused() {
+        for header in ["ORDERBOOK-JOURNAL v1 T\n".to_string(), format!("{MAGIC} v99 T {}\n", config()), "not a journal\n".to_string()] {
+            let path = write("version", &[header, record(1, "phase CONTINUOUS")]);
+            assert!(matches!(read_journal(&path), Err(JournalError::UnsupportedVersion(_))));
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn other_settings_refused() {
+        let path = write("config", &[header("T")]);
+        let instrument = Instrument::new("T".to_string(), "0.05".parse().unwrap(), 1, 1, 1000, "100.00".parse().unwrap(), 5000, 0);
+        let config = BookConfig::of(&OrderBook::new(instrument).with_halt_policy(crate::session::HaltPolicy::Queue));
+        match Journal::open(&path, "T", &config, FsyncPolicy::Always) {
+            Err(JournalError::ConfigMismatch(differences)) => assert_eq!(differences, ["tick_size=0.01 (now 0.05)", "halt_orders=reject (now queue)"]),
+            other => panic!("{other:?}"),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recovered_orders_of_a_login_stay_with_it() {
+        let timestamp = DateTime::parse_from_rfc3339("2026-01-02T09:00:00Z").unwrap().with_timezone(&Utc);
+        let alice = Owner::login("alice").unwrap();
+        let anonymous = Owner::Connection(([127, 0, 0, 1], 4000).into());
+        let entries = [
+            JournalEntry::Request(alice.clone(), 1, "buy limit T 100.00 5 a gtc".to_string()),
+            JournalEntry::Request(alice.clone(), 2, "buy limit T 99.00 5 b gtc".to_string()),
+            JournalEntry::Request(alice.clone(), 3, "sell stop T 90.00 5 c".to_string()),
+            JournalEntry::Request(anonymous, 4, "buy limit T 98.00 5 d gtc".to_string()),
+            JournalEntry::CancelRecovered,
+        ];
+        let records: Vec<JournalRecord> = entries.into_iter().enumerate().map(|(seq, entry)| JournalRecord::new(seq as u64 + 1, timestamp, entry)).collect();
+
+        let instrument = Instrument::new("T".to_string(), "0.01".parse().unwrap(), 1, 1, 1000, "100.00".parse().unwrap(), 5000, 0);
+        let mut book = OrderBook::new(instrument);
+        let logins = Logins::new();
+        let (tx_recovered, _rx_recovered) = mpsc::unbounded_channel();
+        let (tx_market_data, _rx_market_data) = mpsc::unbounded_channel();
+        replay(&mut book, &records, &logins, &tx_recovered, &tx_market_data).unwrap();
+        assert_eq!(book.working_orders(), 3);
+
+        // Another client cannot touch them, the login can after connecting again.
+        let (other, mut rx_other) = crate::testing::client(5);
+        let (client, mut rx) = crate::testing::login(&logins, "alice", 6);
+        let counter = Arc::new(AtomicU64::new(0));
+        for (client, line) in [(&other, "cancel T 1"), (&client, "cancel T 3"), (&client, "amend T 2 99.00 3"), (&other, "sell limit T 100.00 2 e gtc")] {
+            let mut request = protocol::parse_request(line, client.clone(), timestamp).unwrap();
+            if let Some(new_order) = request.new_order_mut() {
+                new_order.set_order_id(5);
+            }
+            book.handle_order(request, tx_market_data.clone(), counter.clone());
+        }
+
+        let reports = crate::testing::reports(&mut rx_other);
+        assert!(matches!(reports[0], ExecutionReport::Rejected(_, RejectReason::UnknownOrder)));
+        let reports = crate::testing::reports(&mut rx);
+        assert!(matches!(&reports[..], [ExecutionReport::Canceled(canceled, _), ExecutionReport::Replaced(replaced), ExecutionReport::PartialFill(filled)]
+            if canceled.order_id() == 3 && replaced.order_id() == 2 && filled.order_id() == 1), "{reports:?}");
+        assert_eq!(book.working_orders(), 2);
+        assert_eq!(book.get_order(2).unwrap().remaining_size(), 3);
+    }
+
+    /// Book driven the way the server drives it, journaling every request and timer event
+    /// before the book acts on it.
+    struct Session {
+        book: OrderBook,
+        journal: Journal,
+        clock: DateTime<Utc>,
+        next_order_id: u64,
+        tx_market_data: mpsc::UnboundedSender<MarketData>,
+    }
+
+    impl Session {
+        fn request(&mut self, client: &crate::client_handler::Client, line: &str) {
+            self.clock += chrono::TimeDelta::seconds(1);
+            let mut request = protocol::parse_book_request(line, client.clone(), self.clock).unwrap();
+            if let Some(new_order) = request.new_order_mut() {
+                new_order.set_order_id(self.next_order_id);
+                self.next_order_id += 1;
+            }
+            self.journal.append_request(&request).unwrap();
+            self.book.handle_order(request, self.tx_market_data.clone(), Arc::new(AtomicU64::new(0)));
+        }
+
+        fn event(&mut self, after: chrono::TimeDelta, entry: JournalEntry) {
+            self.clock += after;
+            self.journal.append(self.clock, entry.clone()).unwrap();
+            match entry {
+                JournalEntry::Phase(phase) => self.book.set_phase(phase, self.clock, &self.tx_market_data),
+                JournalEntry::EndVolatilityAuction => self.book.end_volatility_auction(self.clock, &self.tx_market_data),
+                JournalEntry::ExpireDayOrders => self.book.expire_day_orders(self.clock, &self.tx_market_data),
+                other => panic!("{other:?}"),
+            }
+        }
+    }
+
+    /// What a book looks like from outside: its market data views and full dump, its
+    /// phase, prices and ids, and every report and update it sent.
+    fn observe(book: &OrderBook, rx_reports: &mut mpsc::UnboundedReceiver<ClientMessage>, rx_market_data: &mut mpsc::UnboundedReceiver<MarketData>) -> Vec<String> {
+        let mut queues = crate::market_data::QueueBook::new("T".to_string());
+        let mut seen: Vec<String> = std::iter::from_fn(|| rx_market_data.try_recv().ok())
+            .inspect(|update| {
+                if update.feed() == crate::market_data::Feed::Orders {
+                    queues.apply(update).unwrap();
+                }
+            })
+            .map(|update| protocol::serialize_market_data(&update))
+            .collect();
+        seen.extend(crate::testing::reports(rx_reports).iter().map(protocol::serialize_report));
+        seen.push(protocol::serialize_depth(&book.depth(usize::MAX)));
+        seen.push(protocol::serialize_order_snapshot(&queues.snapshot()));
+        seen.extend(book.working().iter().map(protocol::serialize_working_order));
+        seen.push(format!("{:?} {:?} {:?} {} {}", book.phase(), book.last_price(), book.volatility_auction_end(), book.next_exec_id(), book.next_trade_id()));
+        seen
+    }
+
+    #[test]
+    fn replay_rebuilds_the_book_it_journaled() {
+        let instrument = || Instrument::new("T".to_string(), "0.01".parse().unwrap(), 1, 1, 1000, "100.00".parse().unwrap(), 5000, 1000);
+        let new_book = || OrderBook::new(instrument()).with_halt_policy(crate::session::HaltPolicy::Queue).with_volatility_auction(chrono::TimeDelta::seconds(60));
+        let path = path("round-trip");
+        let (journal, _) = Journal::open(&path, "T", &BookConfig::of(&new_book()), FsyncPolicy::Never).unwrap();
+        let (tx_reports, mut rx_reports) = mpsc::unbounded_channel();
+        let (tx_market_data, mut rx_market_data) = mpsc::unbounded_channel();
+        let mut session = Session {
+            book: new_book(),
+            journal,
+            clock: DateTime::parse_from_rfc3339("2026-01-02T09:00:00Z").unwrap().with_timezone(&Utc),
+            next_order_id: 1,
+            tx_market_data,
+        };
+        // Every report goes to one channel, so their order can be compared with the replay.
+        let x = crate::client_handler::Client::new(tx_reports.clone(), ([127, 0, 0, 1], 4000).into());
+        let y = Logins::new().login("alice", ([127, 0, 0, 1], 4001).into(), tx_reports).unwrap();
+        let minute = chrono::TimeDelta::minutes(1);
+
+        // Icebergs, hidden and post-only orders, and stops that a trade sets off.
+        session.request(&y, "sell limit T 101.00 10 a1 display=3");
+        session.request(&x, "sell limit T 101.00 5 a2 hidden");
+        session.request(&x, "buy limit T 99.00 5 b1 postonly");
+        session.request(&y, "buy limit T 101.00 2 b2 postonly=reprice");
+        session.request(&x, "sell stop T 99.50 3 s1");
+        session.request(&y, "buy stoplimit T 104.00 104.00 2 s2 day");
+        session.request(&x, "buy limit T 101.00 4 b3");
+        session.request(&y, "amend T 1 101.00 8");
+        session.request(&x, "sell market T 6 m1");
+        session.request(&x, "buy limit T 98.50 2 b4 hidden");
+
+        // Orders and amends held during a halt.
+        session.request(&x, "halt T");
+        session.request(&y, "buy limit T 100.00 3 h1");
+        session.request(&y, "amend T 1 101.00 6");
+        session.request(&x, "cancel T 2");
+        session.request(&x, "resume T");
+
+        // A trade outside the volatility band starts an auction, which a halt interrupts.
+        session.request(&y, "buy limit T 112.00 6 v1");
+        session.request(&x, "sell limit T 111.00 2 v2");
+        session.request(&x, "halt T");
+        session.request(&x, "resume T");
+        session.request(&x, "sell limit T 110.00 1 v3");
+        session.event(minute, JournalEntry::EndVolatilityAuction);
+
+        // A scheduled call, the end of the session and a halt still in force.
+        session.event(minute, JournalEntry::Phase(TradingPhase::ClosingAuction));
+        session.request(&x, "sell limit T 100.00 4 c1 day");
+        session.event(minute, JournalEntry::Phase(TradingPhase::Closed));
+        session.event(minute, JournalEntry::ExpireDayOrders);
+        session.event(minute, JournalEntry::Phase(TradingPhase::Continuous));
+        session.request(&y, "sell limit T 102.00 3 e1 display=1");
+        session.request(&x, "sell stop T 90.00 1 s3");
+        session.request(&x, "halt T");
+        session.request(&y, "buy limit T 97.00 2 h2");
+        session.request(&y, "amend T 1 101.00 5");
+        drop(session.journal);
+
+        let live = observe(&session.book, &mut rx_reports, &mut rx_market_data);
+        for state in ["state=resting", "state=stop", "state=held", "hidden", "display=", "phase=VOLATILITY_AUCTION", "aggressor=none"] {
+            assert!(live.iter().any(|line| line.contains(state)), "no {state} in {live:#?}");
+        }
+
+        let contents = read_journal(&path).unwrap();
+        assert_eq!(contents.last_order_id() + 1, session.next_order_id);
+        let mut replayed = new_book();
+        let (tx_reports, mut rx_reports) = mpsc::unbounded_channel();
+        let (tx_market_data, mut rx_market_data) = mpsc::unbounded_channel();
+        replay(&mut replayed, contents.records(), &Logins::new(), &tx_reports, &tx_market_data).unwrap();
+        assert_eq!(observe(&replayed, &mut rx_reports, &mut rx_market_data), live);
+        fs::remove_file(&path).unwrap();
+    }
+}
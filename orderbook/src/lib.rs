@@ -1,3 +1,18 @@
 pub mod orders;
 pub mod client_handler;
-pub mod orderbook;
\ No newline at end of file
+pub mod orderbook;
+pub mod price_level;
+pub mod execution_report;
+pub mod trade;
+pub mod protocol;
+pub mod stop_book;
+pub mod registry;
+pub mod instrument;
+pub mod price;
+pub mod market_data;
+pub mod publisher;
+pub mod session;
+pub mod allocation;
+pub mod journal;
+#[cfg(test)]
+mod testing;
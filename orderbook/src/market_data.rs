@@ -0,0 +1,587 @@
+use core::fmt;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{orders::MarketSide, price::Price, session::TradingPhase, trade::Trade};
+
+/// One aggregated price level: only displayed quantity counts, so hidden orders and the
+/// reserve of iceberg orders are left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthLevel {
+    price: Price,
+    quantity: usize,
+    order_count: usize,
+}
+
+impl DepthLevel {
+    pub fn new(price: Price, quantity: usize, order_count: usize) -> Self {
+        DepthLevel {
+            price,
+            quantity,
+            order_count,
+        }
+    }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
+
+    pub fn quantity(&self) -> usize {
+        self.quantity
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.order_count
+    }
+}
+
+/// Level-2 snapshot of the top of a book, best price first on each side. `seq` is the
+/// sequence number of the last update it includes, so a feed resumes at `seq + 1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Depth {
+    symbol: String,
+    seq: u64,
+    bids: Vec<DepthLevel>,
+    asks: Vec<DepthLevel>,
+    timestamp: DateTime<Utc>,
+}
+
+impl Depth {
+    pub fn new(symbol: String, seq: u64, bids: Vec<DepthLevel>, asks: Vec<DepthLevel>, timestamp: DateTime<Utc>) -> Self {
+        Depth {
+            symbol,
+            seq,
+            bids,
+            asks,
+            timestamp,
+        }
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn bids(&self) -> &[DepthLevel] {
+        &self.bids
+    }
+
+    pub fn asks(&self) -> &[DepthLevel] {
+        &self.asks
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelAction {
+    Add,
+    Change,
+    Delete,
+}
+
+/// Change to one aggregated price level. A deleted level carries no quantity or orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelUpdate {
+    side: MarketSide,
+    action: LevelAction,
+    level: DepthLevel,
+}
+
+impl LevelUpdate {
+    pub fn new(side: MarketSide, action: LevelAction, level: DepthLevel) -> Self {
+        LevelUpdate {
+            side,
+            action,
+            level,
+        }
+    }
+
+    pub fn side(&self) -> MarketSide {
+        self.side
+    }
+
+    pub fn action(&self) -> LevelAction {
+        self.action
+    }
+
+    pub fn level(&self) -> DepthLevel {
+        self.level
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderAction {
+    Add,
+    Modify,
+    Execute,
+    Delete,
+}
+
+/// Change to one displayed order. `quantity` is the displayed quantity for an add or a
+/// modify, the quantity traded for an execute and zero for a delete. An add always joins
+/// the back of its price level, a modify keeps the order's place in the queue, and an
+/// order leaves the book only through a delete, even once fully executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderUpdate {
+    order_id: u64,
+    side: MarketSide,
+    price: Price,
+    quantity: usize,
+    action: OrderAction,
+}
+
+impl OrderUpdate {
+    pub fn new(order_id: u64, side: MarketSide, price: Price, quantity: usize, action: OrderAction) -> Self {
+        OrderUpdate {
+            order_id,
+            side,
+            price,
+            quantity,
+            action,
+        }
+    }
+
+    pub fn order_id(&self) -> u64 {
+        self.order_id
+    }
+
+    pub fn side(&self) -> MarketSide {
+        self.side
+    }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
+
+    pub fn quantity(&self) -> usize {
+        self.quantity
+    }
+
+    pub fn action(&self) -> OrderAction {
+        self.action
+    }
+}
+
+/// Outcome of an auction call if it ended now: the equilibrium price and the quantity
+/// that would trade there, both absent while the book does not cross.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Indicative {
+    price: Option<Price>,
+    volume: usize,
+}
+
+impl Indicative {
+    pub fn new(price: Option<Price>, volume: usize) -> Self {
+        Indicative {
+            price,
+            volume,
+        }
+    }
+
+    pub fn price(&self) -> Option<Price> {
+        self.price
+    }
+
+    pub fn volume(&self) -> usize {
+        self.volume
+    }
+}
+
+/// The two feeds of a book: aggregated levels with trade prints, phase changes and
+/// auction indications (level 2), and order-by-order events (level 3). Each is sequenced
+/// on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feed {
+    Levels,
+    Orders,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarketDataEvent {
+    Level(LevelUpdate),
+    Trade(Trade),
+    Phase(TradingPhase),
+    Indicative(Indicative),
+    Order(OrderUpdate),
+}
+
+impl MarketDataEvent {
+    pub fn feed(&self) -> Feed {
+        match self {
+            MarketDataEvent::Level(_) | MarketDataEvent::Trade(_) | MarketDataEvent::Phase(_) | MarketDataEvent::Indicative(_) => Feed::Levels,
+            MarketDataEvent::Order(_) => Feed::Orders,
+        }
+    }
+}
+
+/// One incremental update of a book's public feed. Sequence numbers start at 1 and
+/// count every update of the symbol on the same feed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarketData {
+    symbol: String,
+    seq: u64,
+    event: MarketDataEvent,
+    timestamp: DateTime<Utc>,
+}
+
+impl MarketData {
+    pub fn new(symbol: String, seq: u64, event: MarketDataEvent, timestamp: DateTime<Utc>) -> Self {
+        MarketData {
+            symbol,
+            seq,
+            event,
+            timestamp,
+        }
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn event(&self) -> &MarketDataEvent {
+        &self.event
+    }
+
+    pub fn feed(&self) -> Feed {
+        self.event.feed()
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+}
+
+/// An update arrived with a sequence number other than the next one expected, so the
+/// levels built so far can no longer be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub expected: u64,
+    pub received: u64,
+}
+
+impl fmt::Display for SequenceGap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected seq {}, received {}", self.expected, self.received)
+    }
+}
+
+/// Aggregated price levels of one book as rebuilt from its feed, which is all a market
+/// data consumer can see of it.
+#[derive(Debug, Clone)]
+pub struct LevelBook {
+    symbol: String,
+    bids: BTreeMap<Price, DepthLevel>,
+    asks: BTreeMap<Price, DepthLevel>,
+    seq: u64,
+    timestamp: DateTime<Utc>,
+}
+
+impl LevelBook {
+    pub fn new(symbol: String) -> Self {
+        LevelBook {
+            symbol,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            seq: 0,
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    /// Sequence number of the last update applied, 0 before the first one.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn level(&self, side: MarketSide, price: Price) -> Option<DepthLevel> {
+        match side {
+            MarketSide::Bid => self.bids.get(&price).copied(),
+            MarketSide::Ask => self.asks.get(&price).copied(),
+        }
+    }
+
+    /// Applies the next update of the level feed. Updates must arrive in sequence; on a
+    /// gap nothing is applied and the consumer needs a fresh snapshot.
+    pub fn apply(&mut self, update: &MarketData) -> Result<(), SequenceGap> {
+        if update.seq() != self.seq + 1 {
+            return Err(SequenceGap { expected: self.seq + 1, received: update.seq() });
+        }
+        self.seq = update.seq();
+        self.timestamp = *update.timestamp();
+
+        if let MarketDataEvent::Level(level_update) = update.event() {
+            let levels = match level_update.side() {
+                MarketSide::Bid => &mut self.bids,
+                MarketSide::Ask => &mut self.asks,
+            };
+            let level = level_update.level();
+            match level_update.action() {
+                LevelAction::Add | LevelAction::Change => levels.insert(level.price(), level),
+                LevelAction::Delete => levels.remove(&level.price()),
+            };
+        }
+        Ok(())
+    }
+
+    /// Every level on both sides, as of the last update applied (or of creation, before
+    /// the first one).
+    pub fn snapshot(&self) -> Depth {
+        let bids = self.bids.values().rev().copied().collect();
+        let asks = self.asks.values().copied().collect();
+        Depth::new(self.symbol.clone(), self.seq, bids, asks, self.timestamp)
+    }
+}
+
+/// A displayed order in the queue of its price level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuedOrder {
+    order_id: u64,
+    price: Price,
+    quantity: usize,
+}
+
+impl QueuedOrder {
+    pub fn new(order_id: u64, price: Price, quantity: usize) -> Self {
+        QueuedOrder {
+            order_id,
+            price,
+            quantity,
+        }
+    }
+
+    pub fn order_id(&self) -> u64 {
+        self.order_id
+    }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
+
+    pub fn quantity(&self) -> usize {
+        self.quantity
+    }
+}
+
+/// Level-3 snapshot of a book: every displayed order, best price first and in queue
+/// order within a price. `seq` is the last order feed update it includes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderSnapshot {
+    symbol: String,
+    seq: u64,
+    bids: Vec<QueuedOrder>,
+    asks: Vec<QueuedOrder>,
+    timestamp: DateTime<Utc>,
+}
+
+impl OrderSnapshot {
+    pub fn new(symbol: String, seq: u64, bids: Vec<QueuedOrder>, asks: Vec<QueuedOrder>, timestamp: DateTime<Utc>) -> Self {
+        OrderSnapshot {
+            symbol,
+            seq,
+            bids,
+            asks,
+            timestamp,
+        }
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn bids(&self) -> &[QueuedOrder] {
+        &self.bids
+    }
+
+    pub fn asks(&self) -> &[QueuedOrder] {
+        &self.asks
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+}
+
+/// Queues of displayed orders of one book as rebuilt from its order feed.
+#[derive(Debug, Clone)]
+pub struct QueueBook {
+    symbol: String,
+    bids: BTreeMap<Price, VecDeque<QueuedOrder>>,
+    asks: BTreeMap<Price, VecDeque<QueuedOrder>>,
+    seq: u64,
+    timestamp: DateTime<Utc>,
+}
+
+impl QueueBook {
+    pub fn new(symbol: String) -> Self {
+        QueueBook {
+            symbol,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            seq: 0,
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    /// Sequence number of the last update applied, 0 before the first one.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Applies the next update of the order feed. Updates must arrive in sequence; on a
+    /// gap nothing is applied and the consumer needs a fresh snapshot.
+    pub fn apply(&mut self, update: &MarketData) -> Result<(), SequenceGap> {
+        if update.seq() != self.seq + 1 {
+            return Err(SequenceGap { expected: self.seq + 1, received: update.seq() });
+        }
+        self.seq = update.seq();
+        self.timestamp = *update.timestamp();
+
+        let MarketDataEvent::Order(order_update) = update.event() else {
+            return Ok(());
+        };
+        let levels = match order_update.side() {
+            MarketSide::Bid => &mut self.bids,
+            MarketSide::Ask => &mut self.asks,
+        };
+        let order_id = order_update.order_id();
+        let price = order_update.price();
+
+        if order_update.action() == OrderAction::Add {
+            levels
+                .entry(price)
+                .or_default()
+                .push_back(QueuedOrder::new(order_id, price, order_update.quantity()));
+            return Ok(());
+        }
+
+        let Some(queue) = levels.get_mut(&price) else {
+            return Ok(());
+        };
+        let Some(position) = queue.iter().position(|o| o.order_id == order_id) else {
+            return Ok(());
+        };
+        match order_update.action() {
+            OrderAction::Add => {},
+            OrderAction::Modify => queue[position].quantity = order_update.quantity(),
+            OrderAction::Execute => queue[position].quantity = queue[position].quantity.saturating_sub(order_update.quantity()),
+            OrderAction::Delete => {
+                queue.remove(position);
+                if queue.is_empty() {
+                    levels.remove(&price);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Every displayed order on both sides, as of the last update applied (or of
+    /// creation, before the first one).
+    pub fn snapshot(&self) -> OrderSnapshot {
+        let bids = self.bids.values().rev().flatten().copied().collect();
+        let asks = self.asks.values().flatten().copied().collect();
+        OrderSnapshot::new(self.symbol.clone(), self.seq, bids, asks, self.timestamp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(s: &str) -> Price {
+        s.parse().unwrap()
+    }
+
+    fn update(seq: u64, event: MarketDataEvent) -> MarketData {
+        MarketData::new("T".to_string(), seq, event, DateTime::UNIX_EPOCH)
+    }
+
+    fn level(seq: u64, side: MarketSide, action: LevelAction, price_str: &str, quantity: usize, order_count: usize) -> MarketData {
+        update(seq, MarketDataEvent::Level(LevelUpdate::new(side, action, DepthLevel::new(price(price_str), quantity, order_count))))
+    }
+
+    #[test]
+    fn level_book_applies_updates_in_sequence() {
+        let mut book = LevelBook::new("T".to_string());
+        book.apply(&level(1, MarketSide::Bid, LevelAction::Add, "100.00", 5, 1)).unwrap();
+        book.apply(&level(2, MarketSide::Bid, LevelAction::Add, "101.00", 3, 1)).unwrap();
+        book.apply(&level(3, MarketSide::Ask, LevelAction::Add, "102.00", 4, 2)).unwrap();
+        book.apply(&level(4, MarketSide::Bid, LevelAction::Change, "100.00", 8, 2)).unwrap();
+        book.apply(&update(5, MarketDataEvent::Phase(TradingPhase::Continuous))).unwrap();
+        book.apply(&level(6, MarketSide::Bid, LevelAction::Delete, "101.00", 0, 0)).unwrap();
+
+        let snapshot = book.snapshot();
+        assert_eq!(snapshot.seq(), 6);
+        assert_eq!(snapshot.bids(), [DepthLevel::new(price("100.00"), 8, 2)]);
+        assert_eq!(snapshot.asks(), [DepthLevel::new(price("102.00"), 4, 2)]);
+        assert_eq!(book.level(MarketSide::Bid, price("101.00")), None);
+    }
+
+    #[test]
+    fn level_book_refuses_gaps() {
+        let mut book = LevelBook::new("T".to_string());
+        book.apply(&level(1, MarketSide::Bid, LevelAction::Add, "100.00", 5, 1)).unwrap();
+
+        for seq in [1, 3] {
+            let gap = book.apply(&level(seq, MarketSide::Bid, LevelAction::Change, "100.00", 9, 2));
+            assert_eq!(gap, Err(SequenceGap { expected: 2, received: seq }));
+        }
+        assert_eq!(book.seq(), 1);
+        assert_eq!(book.level(MarketSide::Bid, price("100.00")), Some(DepthLevel::new(price("100.00"), 5, 1)));
+
+        book.apply(&level(2, MarketSide::Bid, LevelAction::Change, "100.00", 9, 2)).unwrap();
+        assert_eq!(book.seq(), 2);
+    }
+
+    fn order(seq: u64, order_id: u64, action: OrderAction, price_str: &str, quantity: usize) -> MarketData {
+        update(seq, MarketDataEvent::Order(OrderUpdate::new(order_id, MarketSide::Ask, price(price_str), quantity, action)))
+    }
+
+    fn queue(snapshot: &OrderSnapshot) -> Vec<(u64, usize)> {
+        snapshot.asks().iter().map(|order| (order.order_id(), order.quantity())).collect()
+    }
+
+    #[test]
+    fn queue_book_follows_orders() {
+        let mut book = QueueBook::new("T".to_string());
+        book.apply(&order(1, 1, OrderAction::Add, "100.00", 5)).unwrap();
+        book.apply(&order(2, 2, OrderAction::Add, "100.00", 4)).unwrap();
+        book.apply(&order(3, 3, OrderAction::Add, "99.00", 2)).unwrap();
+        assert_eq!(queue(&book.snapshot()), [(3, 2), (1, 5), (2, 4)]);
+
+        // A modify and an execute keep the place in the queue.
+        book.apply(&order(4, 1, OrderAction::Modify, "100.00", 3)).unwrap();
+        book.apply(&order(5, 2, OrderAction::Execute, "100.00", 1)).unwrap();
+        assert_eq!(queue(&book.snapshot()), [(3, 2), (1, 3), (2, 3)]);
+
+        book.apply(&order(6, 3, OrderAction::Delete, "99.00", 0)).unwrap();
+        book.apply(&order(7, 1, OrderAction::Delete, "100.00", 0)).unwrap();
+        book.apply(&order(8, 1, OrderAction::Add, "100.00", 3)).unwrap();
+        let snapshot = book.snapshot();
+        assert_eq!(queue(&snapshot), [(2, 3), (1, 3)]);
+        assert_eq!(snapshot.seq(), 8);
+
+        assert_eq!(book.apply(&order(10, 2, OrderAction::Delete, "100.00", 0)), Err(SequenceGap { expected: 9, received: 10 }));
+        assert_eq!(queue(&book.snapshot()), [(2, 3), (1, 3)]);
+    }
+}
@@ -1,222 +1,2065 @@
 use core::fmt;
-use std::{collections::{BTreeMap, VecDeque}, sync::{atomic::AtomicU64, Arc}};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::{collections::{BTreeMap, HashMap}, sync::{atomic::AtomicU64, Arc}};
 use tokio::sync::mpsc;
 
-use crate::orders::{LimitOrder, MarketOrder, MarketSide, Orders};
+use crate::{
+    allocation::{AllocationPolicy, Fifo},
+    client_handler::{Client, ClientMessage, Owner},
+    execution_report::{CancelReason, ExecutionDetails, ExecutionReport, RejectReason},
+    instrument::Instrument,
+    market_data::{Depth, DepthLevel, Feed, Indicative, LevelAction, LevelBook, LevelUpdate, MarketData, MarketDataEvent, OrderAction, OrderUpdate},
+    price::Price,
+    orders::{AmendOrder, CancelOrder, ControlAction, ControlRequest, LimitOrder, MarketOrder, MarketSide, Order, Orders, PostOnly, PriceProtection, SelfTradePrevention, TimeInForce},
+    price_level::{OrderArena, OrderHandle, PriceLevel},
+    session::{HaltPolicy, TradingPhase},
+    stop_book::{PendingStop, StopBook},
+    trade::Trade,
+};
 
+/// An order still working in a book, as listed by `OrderBook::working`.
+#[derive(Debug)]
+pub enum WorkingOrder<'a> {
+    Resting(&'a LimitOrder),
+    Stop(&'a PendingStop),
+    /// New order or amend held during a halt.
+    Held(&'a Orders),
+}
+
+/// Book of a single instrument. Order ids are assigned before orders reach it, by the
+/// `BookRegistry`, so they stay unique across every book.
 #[derive(Debug)]
 pub struct OrderBook {
-    bids: BTreeMap<usize, VecDeque<LimitOrder>>,
-    asks: BTreeMap<usize, VecDeque<LimitOrder>>,
+    instrument: Instrument,
+    bids: BTreeMap<Price, PriceLevel>,
+    asks: BTreeMap<Price, PriceLevel>,
+    orders: OrderArena,
+    index: HashMap<u64, OrderHandle>,
+    stops: StopBook,
+    last_price: Option<Price>,
+    /// Levels as last published on the market data feed.
+    published: LevelBook,
+    /// Levels touched since the feed was last brought up to date.
+    changed_levels: Vec<(MarketSide, Price)>,
+    /// Order feed events not published yet, in the order the book applied them.
+    order_events: Vec<OrderUpdate>,
+    /// Sequence number of the last order feed event published.
+    order_feed_seq: u64,
+    phase: TradingPhase,
+    /// Phase the schedule is in, which the book returns to after a halt or a volatility
+    /// auction.
+    scheduled: TradingPhase,
+    /// Phase a halt interrupted.
+    interrupted: TradingPhase,
+    /// Indicative price and volume last published during the current auction call.
+    indicative: Option<Indicative>,
+    volatility_auction: TimeDelta,
+    volatility_auction_end: Option<DateTime<Utc>>,
+    halt_policy: HaltPolicy,
+    /// How an incoming order's quantity is shared among the orders at a price level.
+    allocation: Box<dyn AllocationPolicy>,
+    /// New orders and amends held during a halt, in arrival order.
+    held: Vec<Orders>,
+    next_exec_id: u64,
+    next_trade_id: u64,
+    /// Timestamp of the order being handled, used to stamp every report it produces.
+    clock: DateTime<Utc>,
 }
 
 impl OrderBook {
-    pub fn new() -> Self {
+    pub fn new(instrument: Instrument) -> Self {
+        let published = LevelBook::new(instrument.symbol().clone());
         OrderBook {
+            instrument,
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
+            orders: OrderArena::new(),
+            index: HashMap::new(),
+            stops: StopBook::new(),
+            last_price: None,
+            published,
+            changed_levels: Vec::new(),
+            order_events: Vec::new(),
+            order_feed_seq: 0,
+            phase: TradingPhase::Continuous,
+            scheduled: TradingPhase::Continuous,
+            interrupted: TradingPhase::Continuous,
+            indicative: None,
+            volatility_auction: TimeDelta::minutes(5),
+            volatility_auction_end: None,
+            halt_policy: HaltPolicy::default(),
+            allocation: Box::new(Fifo),
+            held: Vec::new(),
+            next_exec_id: 1,
+            next_trade_id: 1,
+            clock: DateTime::<Utc>::MIN_UTC,
+        }
+    }
+
+    /// Length of the volatility auction started when a trade would print outside the
+    /// instrument's volatility band (five minutes by default).
+    pub fn with_volatility_auction(mut self, duration: TimeDelta) -> Self {
+        self.volatility_auction = duration;
+        self
+    }
+
+    pub fn with_halt_policy(mut self, halt_policy: HaltPolicy) -> Self {
+        self.halt_policy = halt_policy;
+        self
+    }
+
+    /// Matches with `allocation` instead of price-time priority.
+    pub fn with_allocation(mut self, allocation: Box<dyn AllocationPolicy>) -> Self {
+        self.allocation = allocation;
+        self
+    }
+
+    pub fn symbol(&self) -> &String {
+        self.instrument.symbol()
+    }
+
+    pub fn instrument(&self) -> &Instrument {
+        &self.instrument
+    }
+
+    pub fn allocation(&self) -> &dyn AllocationPolicy {
+        self.allocation.as_ref()
+    }
+
+    pub fn halt_policy(&self) -> HaltPolicy {
+        self.halt_policy
+    }
+
+    /// Length of the volatility auctions the circuit breaker starts.
+    pub fn volatility_auction(&self) -> TimeDelta {
+        self.volatility_auction
+    }
+
+    /// Number of orders currently resting in the book.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn phase(&self) -> TradingPhase {
+        self.phase
+    }
+
+    /// Price of the most recent trade, which drives stop triggers.
+    pub fn last_price(&self) -> Option<Price> {
+        self.last_price
+    }
+
+    /// Looks up a resting order by its exchange-assigned id.
+    pub fn get_order(&self, order_id: u64) -> Option<&LimitOrder> {
+        self.index
+            .get(&order_id)
+            .and_then(|&handle| self.orders.get(handle))
+    }
+
+    /// Every working order, hidden ones and iceberg reserves included, unlike the market
+    /// data views: resting bids then asks, best price first and in queue order within a
+    /// price, then stops by order id, then what was held during a halt in arrival order.
+    pub fn working(&self) -> Vec<WorkingOrder<'_>> {
+        let bids = self.bids.values().rev().flat_map(|level| level.iter(&self.orders));
+        let asks = self.asks.values().flat_map(|level| level.iter(&self.orders));
+        bids.chain(asks)
+            .map(|(_, order)| WorkingOrder::Resting(order))
+            .chain(self.stops.iter().map(WorkingOrder::Stop))
+            .chain(self.held.iter().map(WorkingOrder::Held))
+            .collect()
+    }
+
+    /// Exec id the next execution report will carry.
+    pub fn next_exec_id(&self) -> u64 {
+        self.next_exec_id
+    }
+
+    /// Trade id the next trade will carry.
+    pub fn next_trade_id(&self) -> u64 {
+        self.next_trade_id
+    }
+
+    /// Level-2 view of the book: the best `levels` prices on each side, best first, with
+    /// the displayed quantity and number of displayed orders at each. Hidden orders do
+    /// not count, so a level holding only hidden orders is skipped.
+    pub fn depth(&self, levels: usize) -> Depth {
+        let bids = self.aggregate(self.bids.iter().rev(), levels);
+        let asks = self.aggregate(self.asks.iter(), levels);
+        Depth::new(self.symbol().clone(), self.published.seq(), bids, asks, self.clock)
+    }
+
+    /// Price and volume the book would uncross at if the current call ended now.
+    pub fn indicative(&self) -> Indicative {
+        match self.equilibrium() {
+            Some((price, volume)) => Indicative::new(Some(price), volume),
+            None => Indicative::new(None, 0),
+        }
+    }
+
+    /// End of the volatility auction in progress, if any.
+    pub fn volatility_auction_end(&self) -> Option<DateTime<Utc>> {
+        self.volatility_auction_end.filter(|_| self.phase == TradingPhase::VolatilityAuction)
+    }
+
+    /// Moves the book to the next phase of its schedule. A halted book stays halted and
+    /// enters the scheduled phase once trading resumes.
+    pub fn set_phase(&mut self, phase: TradingPhase, timestamp: DateTime<Utc>, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        self.clock = timestamp;
+        self.scheduled = phase;
+        if phase == self.phase || self.phase == TradingPhase::Halted {
+            return;
         }
+        self.enter_phase(phase, tx_market_data);
     }
 
-    pub fn handle_order(&mut self, order: Orders, tx_price: mpsc::UnboundedSender<usize>, counter: Arc<AtomicU64>) {
+    /// Uncrosses the book once the volatility auction in progress is over and returns to
+    /// the scheduled phase.
+    pub fn end_volatility_auction(&mut self, timestamp: DateTime<Utc>, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        if self.phase != TradingPhase::VolatilityAuction {
+            return;
+        }
+        self.clock = timestamp;
+        self.volatility_auction_end = None;
+        self.enter_phase(self.scheduled, tx_market_data);
+    }
+
+    /// Switches to `phase` and announces it on the feed. Leaving an auction call uncrosses
+    /// the book first, and so does opening continuous trading straight from the pre-open,
+    /// unless trading is being halted. Returns the announcement.
+    fn enter_phase(&mut self, phase: TradingPhase, tx_market_data: &mpsc::UnboundedSender<MarketData>) -> MarketData {
+        let ends_call = self.phase.is_auction() || (self.phase.is_call() && phase == TradingPhase::Continuous);
+        if phase != self.phase && phase != TradingPhase::Halted && ends_call {
+            self.uncross(tx_market_data);
+        }
+        self.phase = phase;
+        self.indicative = None;
+        let update = self.publish(MarketDataEvent::Phase(phase), tx_market_data);
+
+        self.run_triggers(tx_market_data);
+        self.publish_changes(tx_market_data);
+        self.publish_indicative(tx_market_data);
+        update
+    }
+
+    /// Stops continuous trading before a trade prints outside the volatility band.
+    fn start_volatility_auction(&mut self, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        self.volatility_auction_end = Some(self.clock + self.volatility_auction);
+        self.enter_phase(TradingPhase::VolatilityAuction, tx_market_data);
+    }
+
+    /// Suspends trading until an operator resumes it. A call in progress is suspended
+    /// without uncrossing.
+    fn halt(&mut self, request: ControlRequest, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        if matches!(self.phase, TradingPhase::Halted | TradingPhase::Closed) {
+            self.reject_order_id(request.client(), 0, RejectReason::TradingPhase);
+            return;
+        }
+        self.interrupted = self.phase;
+        let update = self.enter_phase(TradingPhase::Halted, tx_market_data);
+        if let Err(e) = request.client().tx().send(update.into()) {
+            eprintln!("Error writing to channel: {e}");
+        }
+    }
+
+    /// Resumes trading in the phase the schedule is in by now, uncrossing first if the
+    /// halt interrupted a call that is over. A volatility auction that has not run its
+    /// time yet carries on instead, and uncrosses when it ends. Orders held during the halt
+    /// are then entered in arrival order, or canceled if that phase does not accept them.
+    fn resume(&mut self, request: ControlRequest, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        if self.phase != TradingPhase::Halted {
+            self.reject_order_id(request.client(), 0, RejectReason::TradingPhase);
+            return;
+        }
+        self.phase = self.interrupted;
+        let auction_running = self.phase == TradingPhase::VolatilityAuction
+            && self.scheduled == TradingPhase::Continuous
+            && self.volatility_auction_end.is_some_and(|end| end > self.clock);
+        let phase = if auction_running {
+            TradingPhase::VolatilityAuction
+        } else {
+            self.scheduled
+        };
+        let update = self.enter_phase(phase, tx_market_data);
+        if let Err(e) = request.client().tx().send(update.into()) {
+            eprintln!("Error writing to channel: {e}");
+        }
+
+        for order in std::mem::take(&mut self.held) {
+            if !self.allowed_in_phase(&order) {
+                self.refuse_held(order);
+                continue;
+            }
+            match order {
+                Orders::Market(market_order) => self.process_market_order(market_order, tx_market_data),
+                Orders::Limit(limit_order) => self.process_limit_order(limit_order, tx_market_data),
+                Orders::Amend(amend_order) => self.replace_order(amend_order, tx_market_data),
+                Orders::Stop(stop_order) => self.process_stop(PendingStop::Stop(stop_order), tx_market_data),
+                Orders::StopLimit(stop_limit_order) => self.process_stop(PendingStop::StopLimit(stop_limit_order), tx_market_data),
+                Orders::Cancel(_) | Orders::Depth(_) | Orders::Control(_) => {},
+            }
+            self.run_triggers(tx_market_data);
+            self.publish_changes(tx_market_data);
+        }
+    }
+
+    /// Keeps a new order or amend until trading resumes. New orders are acknowledged right
+    /// away, so their owners learn the order id and can cancel them while they wait.
+    fn hold(&mut self, order: Orders) {
+        if let Some(new_order) = order.new_order() {
+            self.accept_order(new_order);
+        }
+        self.held.push(order);
+    }
+
+    fn refuse_held(&mut self, order: Orders) {
+        if let Orders::Amend(amend_order) = &order {
+            self.reject_order_id(amend_order.client(), amend_order.order_id(), RejectReason::TradingPhase);
+        } else if let Some(new_order) = order.new_order() {
+            self.cancel_remainder(new_order, CancelReason::TradingPhase);
+        }
+    }
+
+    pub fn handle_order(&mut self, order: Orders, tx_market_data: mpsc::UnboundedSender<MarketData>, counter: Arc<AtomicU64>) {
+        self.clock = *order.timestamp();
+
+        let always_accepted = matches!(order, Orders::Cancel(_) | Orders::Depth(_) | Orders::Control(_));
+        if self.phase == TradingPhase::Halted && self.halt_policy == HaltPolicy::Queue && !always_accepted {
+            self.hold(order);
+            Self::increment(counter);
+            return;
+        }
+        if !self.allowed_in_phase(&order) {
+            self.reject_for_phase(&order);
+            return;
+        }
+
         match order {
             Orders::Market(market_order) => {
-                self.match_order(market_order, tx_price);
+                self.accept_order(&market_order);
+                self.process_market_order(market_order, &tx_market_data);
                 Self::increment(counter);
             },
             Orders::Limit(limit_order) => {
-                self.add_order(limit_order);
+                let repriced = match self.phase {
+                    TradingPhase::Continuous => self.post_only_price(&limit_order).map(|_| ()),
+                    _ => Ok(()),
+                };
+                if let Err(reason) = repriced {
+                    let details = Self::execution(&mut self.next_exec_id, &limit_order, 0, Price::default(), 0, self.clock);
+                    Self::notify(limit_order.client().tx(), ExecutionReport::Rejected(details, reason));
+                    return;
+                }
+                self.accept_order(&limit_order);
+                self.process_limit_order(limit_order, &tx_market_data);
+                Self::increment(counter);
+            },
+            Orders::Cancel(cancel_order) => {
+                self.cancel_order(cancel_order);
+                Self::increment(counter);
+            },
+            Orders::Amend(amend_order) => {
+                self.replace_order(amend_order, &tx_market_data);
                 Self::increment(counter);
+            },
+            Orders::Stop(stop_order) => {
+                self.accept_order(stop_order.order());
+                self.process_stop(PendingStop::Stop(stop_order), &tx_market_data);
+                Self::increment(counter);
+            },
+            Orders::StopLimit(stop_limit_order) => {
+                self.accept_order(stop_limit_order.order());
+                self.process_stop(PendingStop::StopLimit(stop_limit_order), &tx_market_data);
+                Self::increment(counter);
+            },
+            Orders::Depth(request) => {
+                if let Err(e) = request.client().tx().send(self.depth(request.levels()).into()) {
+                    eprintln!("Error writing to channel: {e}");
+                }
+                return;
+            },
+            Orders::Control(request) => match request.action() {
+                ControlAction::Halt => self.halt(request, &tx_market_data),
+                ControlAction::Resume => self.resume(request, &tx_market_data),
+            },
+        }
+
+        self.run_triggers(&tx_market_data);
+        self.publish_changes(&tx_market_data);
+        self.publish_indicative(&tx_market_data);
+    }
+
+    /// Returns true if the current phase accepts the request. A call only collects orders
+    /// that can rest until the uncross, and a closed or halted book only takes cancels.
+    fn allowed_in_phase(&self, order: &Orders) -> bool {
+        match (self.phase, order) {
+            (_, Orders::Cancel(_) | Orders::Depth(_) | Orders::Control(_)) => true,
+            (TradingPhase::Continuous, _) => true,
+            (TradingPhase::Closed | TradingPhase::Halted, _) => false,
+            (_, Orders::Market(_)) => false,
+            (_, Orders::Limit(limit_order)) => matches!(limit_order.time_in_force(), TimeInForce::Gtc | TimeInForce::Day),
+            (_, Orders::Amend(_) | Orders::Stop(_) | Orders::StopLimit(_)) => true,
+        }
+    }
+
+    fn reject_for_phase(&mut self, order: &Orders) {
+        if let Orders::Amend(amend_order) = order {
+            self.reject_order_id(amend_order.client(), amend_order.order_id(), RejectReason::TradingPhase);
+        } else if let Some(new_order) = order.new_order() {
+            let details = Self::execution(&mut self.next_exec_id, new_order, 0, Price::default(), 0, self.clock);
+            Self::notify(new_order.client().tx(), ExecutionReport::Rejected(details, RejectReason::TradingPhase));
+        }
+    }
+
+    /// Parks a stop in the trigger book, or enters it right away if the last traded
+    /// price is already through its trigger and the book is trading continuously.
+    fn process_stop(&mut self, stop: PendingStop, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        let triggered = self.phase == TradingPhase::Continuous && self.last_price
+            .is_some_and(|last_price| StopBook::is_triggered(stop.order().side(), stop.trigger_price(), last_price));
+
+        if triggered {
+            self.enter_stop(stop, tx_market_data);
+        } else {
+            self.stops.insert(stop);
+        }
+    }
+
+    fn enter_stop(&mut self, stop: PendingStop, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        match stop {
+            PendingStop::Stop(stop_order) => self.process_market_order(stop_order.into_order(), tx_market_data),
+            PendingStop::StopLimit(stop_limit_order) => self.process_limit_order(stop_limit_order.into_order(), tx_market_data),
+        }
+    }
+
+    /// Enters every stop fired by the last traded price. Trades printed by those orders
+    /// may fire further stops, so this repeats in waves until the trigger book is quiet.
+    /// Stops only fire during continuous trading, so if one of them starts a volatility
+    /// auction the rest of the wave goes back to the trigger book.
+    fn run_triggers(&mut self, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        if self.phase != TradingPhase::Continuous {
+            return;
+        }
+        while let Some(last_price) = self.last_price {
+            let triggered = self.stops.take_triggered(last_price);
+            if triggered.is_empty() {
+                break;
+            }
+            for stop in triggered {
+                if self.phase == TradingPhase::Continuous {
+                    self.enter_stop(stop, tx_market_data);
+                } else {
+                    self.stops.insert(stop);
+                }
             }
         }
     }
 
-    fn add_order(&mut self, limit_order: LimitOrder) {
-        match limit_order.side() {
-            MarketSide::Ask => {
-                self.asks
-                    .entry(limit_order.price())
-                    .or_insert_with(VecDeque::new)
-                    .push_back(limit_order);
-            },
-            MarketSide::Bid => {
-                self.bids
-                    .entry(limit_order.price())
-                    .or_insert_with(VecDeque::new)
-                    .push_back(limit_order);
+    /// Acknowledges the order. Duplicate client order ids never get here: the
+    /// `BookRegistry` turns them away for the whole session.
+    fn accept_order<O: Order + ?Sized>(&mut self, order: &O) {
+        let details = Self::execution(&mut self.next_exec_id, order, 0, Price::default(), order.remaining_size(), self.clock);
+        Self::notify(order.client().tx(), ExecutionReport::New(details));
+    }
+
+    /// Matches a market order up to its protection price, if any, and cancels whatever
+    /// could not be filled.
+    fn process_market_order(&mut self, mut market_order: MarketOrder, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        let limit_price = self.protection_price(&market_order);
+        let killed = !self.can_fill(&market_order, limit_price);
+        if !killed {
+            self.match_order(&mut market_order, limit_price, tx_market_data);
+        }
+        if market_order.remaining_size() == 0 {
+            return;
+        }
+
+        let reason = if killed {
+            CancelReason::FillOrKill
+        } else if self.phase == TradingPhase::VolatilityAuction {
+            CancelReason::VolatilityAuction
+        } else if self.get_best_opposite(market_order.side()).is_some() {
+            CancelReason::PriceProtection
+        } else {
+            if market_order.side() == MarketSide::Ask {
+                println!("There are no bids!");
+            } else {
+                println!("There are no asks!");
+            }
+            CancelReason::NoLiquidity
+        };
+        self.cancel_remainder(&market_order, reason);
+    }
+
+    /// Worst price a market order may trade at, derived from its protection and, for a
+    /// percentage band, the best opposite price when the order arrives. A band beyond
+    /// the largest price stops there.
+    fn protection_price(&self, market_order: &MarketOrder) -> Option<Price> {
+        match market_order.protection()? {
+            PriceProtection::Limit(price) => Some(price),
+            PriceProtection::BasisPoints(bps) => {
+                let best = self.get_best_opposite(market_order.side())?;
+                let band = best.basis_points(bps);
+                match market_order.side() {
+                    MarketSide::Bid => Some(best.checked_add(band).unwrap_or(Price::new(u64::MAX, best.scale()))),
+                    MarketSide::Ask => Some(best.checked_sub(band).unwrap_or_default()),
+                }
+            }
+        }
+    }
+
+    /// Matches a limit order and then applies its time in force to whatever is left:
+    /// GTC and DAY remainders rest in the book, IOC and FOK remainders are canceled.
+    /// During a call the order rests as it is.
+    fn process_limit_order(&mut self, mut limit_order: LimitOrder, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        if self.phase.is_call() {
+            // Nothing matches during a call; the uncross at its end does.
+            self.add_order(limit_order);
+            return;
+        }
+
+        if limit_order.post_only().is_some() {
+            let Ok(price) = self.post_only_price(&limit_order) else {
+                self.cancel_remainder(&limit_order, CancelReason::PostOnly);
+                return;
+            };
+            limit_order.set_price(price);
+        }
+
+        let limit_price = limit_order.price();
+        let killed = !self.can_fill(&limit_order, Some(limit_price));
+        if !killed {
+            self.match_order(&mut limit_order, Some(limit_price), tx_market_data);
+        }
+        if limit_order.remaining_size() == 0 {
+            return;
+        }
+
+        match limit_order.time_in_force() {
+            TimeInForce::Gtc | TimeInForce::Day => self.add_order(limit_order),
+            _ if self.phase == TradingPhase::VolatilityAuction => self.cancel_remainder(&limit_order, CancelReason::VolatilityAuction),
+            TimeInForce::Ioc => self.cancel_remainder(&limit_order, CancelReason::ImmediateOrCancel),
+            TimeInForce::Fok => self.cancel_remainder(&limit_order, CancelReason::FillOrKill),
+        }
+    }
+
+    /// Price a limit order may rest at without taking liquidity: its own price unless it is
+    /// post-only and crosses the book, in which case it is either repriced one tick away from
+    /// the best opposite price or turned away. A repriced order must still be on the tick
+    /// and inside the instrument's price band.
+    fn post_only_price(&self, limit_order: &LimitOrder) -> Result<Price, RejectReason> {
+        let price = limit_order.price();
+        let Some(post_only) = limit_order.post_only() else {
+            return Ok(price);
+        };
+
+        match self.get_best_opposite(limit_order.side()) {
+            Some(best) if Self::crosses(limit_order.side(), best, Some(price)) => {
+                let repriced = match (post_only, limit_order.side()) {
+                    (PostOnly::Reject, _) => None,
+                    (PostOnly::Reprice, MarketSide::Bid) => best.checked_sub(self.instrument.tick_size()).filter(|price| !price.is_zero()),
+                    (PostOnly::Reprice, MarketSide::Ask) => best.checked_add(self.instrument.tick_size()),
+                };
+                self.instrument.check_price(repriced.ok_or(RejectReason::PostOnly)?)
             },
+            _ => Ok(price),
         }
     }
 
-    fn notify(tx: mpsc::Sender<String>, msg: String) {
-        tokio::spawn(async move {
-            if let Err(e) = tx.send(msg).await {
-                eprintln!("Error writing to channel: {e}");
+    /// FOK orders may only touch the book if the whole remaining size is available at
+    /// acceptable prices inside the volatility band; every other time in force can always
+    /// start matching. Orders of the same account never count, since self-trade
+    /// prevention keeps them from trading, and unless it only cancels the resting side a
+    /// self-match also cuts the incoming order short, so reaching one before enough
+    /// liquidity kills the order.
+    fn can_fill<O: Order>(&self, order: &O, limit_price: Option<Price>) -> bool {
+        if order.time_in_force() != TimeInForce::Fok {
+            return true;
+        }
+        let limit_price = self.band_limit(order.side(), limit_price);
+        let lot_size = self.instrument.lot_size();
+
+        let levels: Box<dyn Iterator<Item = (&Price, &PriceLevel)>> = match order.side() {
+            MarketSide::Bid => Box::new(self.asks.iter()),
+            MarketSide::Ask => Box::new(self.bids.iter().rev()),
+        };
+
+        let mut available = 0;
+        for (&price, level) in levels {
+            if !Self::crosses(order.side(), price, limit_price) {
+                break;
             }
-        });
+            if order.self_trade_prevention() != SelfTradePrevention::CancelOldest {
+                // The first pass over the level shows whether matching would reach one.
+                let sizes: Vec<usize> = level.iter(&self.orders).map(|(_, o)| o.visible_size() / lot_size).collect();
+                let allocations = self.allocation.allocate((order.remaining_size() - available) / lot_size, &sizes);
+                let reaches_own = level
+                    .iter(&self.orders)
+                    .zip(allocations)
+                    .any(|((_, limit_order), lots)| lots > 0 && Self::is_self_trade(order, limit_order));
+                if reaches_own {
+                    return false;
+                }
+            }
+            for (_, limit_order) in level.iter(&self.orders) {
+                if Self::is_self_trade(order, limit_order) {
+                    continue;
+                }
+                available += limit_order.remaining_size();
+                if available >= order.remaining_size() {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Ends the trading session: every resting DAY order is removed from the book and its
+    /// owner receives an `Expired` report.
+    pub fn expire_day_orders(&mut self, timestamp: DateTime<Utc>, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        self.clock = timestamp;
+
+        let mut expiring: Vec<(u64, OrderHandle)> = self.index
+            .iter()
+            .filter(|&(_, &handle)| self.orders.get(handle).unwrap().time_in_force() == TimeInForce::Day)
+            .map(|(&order_id, &handle)| (order_id, handle))
+            .collect();
+        expiring.sort_unstable();
+
+        for (_, handle) in expiring {
+            let limit_order = self.remove_order(handle);
+            let details = Self::execution(&mut self.next_exec_id, &limit_order, 0, Price::default(), 0, self.clock);
+            Self::notify(limit_order.client().tx(), ExecutionReport::Expired(details));
+        }
+
+        for stop in self.stops.take_day_orders() {
+            let details = Self::execution(&mut self.next_exec_id, stop.order(), 0, Price::default(), 0, self.clock);
+            Self::notify(stop.order().client().tx(), ExecutionReport::Expired(details));
+        }
+
+        let (expired, held): (Vec<Orders>, Vec<Orders>) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition(|order| order.new_order().is_some_and(|o| o.time_in_force() == TimeInForce::Day));
+        self.held = held;
+        for order in expired {
+            let new_order = order.new_order().unwrap();
+            let details = Self::execution(&mut self.next_exec_id, new_order, 0, Price::default(), 0, self.clock);
+            Self::notify(new_order.client().tx(), ExecutionReport::Expired(details));
+        }
+
+        self.publish_changes(tx_market_data);
     }
 
-    fn send_price(tx: mpsc::UnboundedSender<usize>, price: usize) {
-        tokio::spawn(async move {
-            if let Err(e) = tx.send(price) {
-                eprintln!("Error writing price on channel: {e}");
+    /// Number of orders still working: resting, waiting for their stop to trigger or held
+    /// during a halt.
+    pub fn working_orders(&self) -> usize {
+        self.index.len() + self.stops.len() + self.held.iter().filter(|order| order.new_order().is_some()).count()
+    }
+
+    /// Number of working orders entered by clients that did not log in, which belong to
+    /// their connection.
+    pub fn anonymous_orders(&self) -> usize {
+        let resting = self.index.values().filter(|&&handle| Self::is_anonymous(self.orders.get(handle).unwrap().client())).count();
+        let stops = self.stops.count_where(|stop| Self::is_anonymous(stop.order().client()));
+        let held = self.held.iter().filter(|order| order.new_order().is_some_and(|o| Self::is_anonymous(o.client()))).count();
+        resting + stops + held
+    }
+
+    /// Cancels every working order entered by a client that did not log in with `reason`:
+    /// resting orders, then stops, each by order id, then orders held during a halt in
+    /// arrival order. Their held amends are rejected, as the order they amend is gone.
+    pub fn cancel_anonymous(&mut self, reason: CancelReason, timestamp: DateTime<Utc>, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        self.clock = timestamp;
+
+        let mut resting: Vec<(u64, OrderHandle)> = self.index
+            .iter()
+            .filter(|&(_, &handle)| Self::is_anonymous(self.orders.get(handle).unwrap().client()))
+            .map(|(&order_id, &handle)| (order_id, handle))
+            .collect();
+        resting.sort_unstable();
+        for (_, handle) in resting {
+            let limit_order = self.remove_order(handle);
+            self.cancel_remainder(&limit_order, reason);
+        }
+
+        for stop in self.stops.take_where(|stop| Self::is_anonymous(stop.order().client())) {
+            self.cancel_remainder(stop.order(), reason);
+        }
+
+        let (anonymous, held): (Vec<Orders>, Vec<Orders>) = std::mem::take(&mut self.held)
+            .into_iter()
+            .partition(|order| Self::is_anonymous(order.client()));
+        self.held = held;
+        for order in anonymous {
+            if let Orders::Amend(amend_order) = &order {
+                self.reject_order_id(amend_order.client(), amend_order.order_id(), RejectReason::UnknownOrder);
+            } else if let Some(new_order) = order.new_order() {
+                self.cancel_remainder(new_order, reason);
             }
+        }
+
+        self.publish_changes(tx_market_data);
+    }
+
+    fn is_anonymous(client: &Client) -> bool {
+        matches!(client.owner(), Owner::Connection(_))
+    }
+
+    /// Removes a resting limit order, a pending stop or an order held during a halt owned
+    /// by the requesting client, dropping its price level if it was the last order there.
+    pub fn cancel_order(&mut self, cancel_order: CancelOrder) {
+        let client = cancel_order.client();
+
+        if let Some(handle) = self.locate_order(client, cancel_order.order_id()) {
+            let limit_order = self.remove_order(handle);
+            let details = Self::execution(&mut self.next_exec_id, &limit_order, 0, Price::default(), 0, self.clock);
+            Self::notify(client.tx(), ExecutionReport::Canceled(details, CancelReason::Requested));
+            return;
+        }
+
+        let held = self.held.iter().position(|order| {
+            order.new_order().is_some_and(|o| o.order_id() == cancel_order.order_id() && o.client().owner() == client.owner())
         });
+        if let Some(position) = held {
+            let order = self.held.remove(position);
+            let new_order = order.new_order().unwrap();
+            let details = Self::execution(&mut self.next_exec_id, new_order, 0, Price::default(), 0, self.clock);
+            Self::notify(client.tx(), ExecutionReport::Canceled(details, CancelReason::Requested));
+            return;
+        }
+
+        let owns_stop = self.stops
+            .get(cancel_order.order_id())
+            .is_some_and(|stop| stop.order().client().owner() == client.owner());
+        if !owns_stop {
+            self.reject_order_id(client, cancel_order.order_id(), RejectReason::UnknownOrder);
+            return;
+        }
+
+        let stop = self.stops.remove(cancel_order.order_id()).unwrap();
+        let details = Self::execution(&mut self.next_exec_id, stop.order(), 0, Price::default(), 0, self.clock);
+        Self::notify(client.tx(), ExecutionReport::Canceled(details, CancelReason::Requested));
+    }
+
+    /// Changes the price and/or total size of a resting limit order owned by the requesting
+    /// client. Reducing the size in place keeps the order's time priority; any other change
+    /// pulls the order and re-enters it like a new order, so it may match and then rests
+    /// at the back of its level.
+    pub fn replace_order(&mut self, amend_order: AmendOrder, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        let client = amend_order.client();
+
+        let Some(handle) = self.locate_order(client, amend_order.order_id()) else {
+            self.reject_order_id(client, amend_order.order_id(), RejectReason::UnknownOrder);
+            return;
+        };
+
+        let limit_order = self.orders.get_mut(handle).unwrap();
+
+        if amend_order.size() <= limit_order.fill_size() {
+            let details = Self::execution(&mut self.next_exec_id, limit_order, 0, Price::default(), limit_order.remaining_size(), self.clock);
+            Self::notify(client.tx(), ExecutionReport::Rejected(details, RejectReason::TooLateToAmend));
+            return;
+        }
+
+        if amend_order.price() == limit_order.price() && amend_order.size() <= limit_order.size() {
+            limit_order.set_size(amend_order.size());
+            self.changed_levels.push((limit_order.side(), limit_order.price()));
+            Self::order_event(&mut self.order_events, limit_order, limit_order.visible_size(), OrderAction::Modify);
+            let details = Self::execution(&mut self.next_exec_id, limit_order, 0, Price::default(), limit_order.remaining_size(), self.clock);
+            Self::notify(client.tx(), ExecutionReport::Replaced(details));
+            return;
+        }
+
+        let limit_order = self.remove_order(handle);
+        let mut replacement = LimitOrder::new(
+            *amend_order.timestamp(),
+            self.symbol().clone(),
+            amend_order.size(),
+            limit_order.fill_size(),
+            limit_order.side(),
+            amend_order.price(),
+            limit_order.client().clone(),
+            limit_order.client_order_id().clone(),
+        )
+        .with_time_in_force(limit_order.time_in_force());
+        if let Some(display_size) = limit_order.display_size() {
+            replacement = replacement.with_display_size(display_size);
+        }
+        if let Some(post_only) = limit_order.post_only() {
+            replacement = replacement.with_post_only(post_only);
+        }
+        if limit_order.is_hidden() {
+            replacement = replacement.with_hidden();
+        }
+        if let Some(account) = limit_order.account() {
+            replacement = replacement
+                .with_account(account.clone())
+                .with_self_trade_prevention(limit_order.self_trade_prevention());
+        }
+        replacement.set_order_id(limit_order.order_id());
+
+        let details = Self::execution(&mut self.next_exec_id, &replacement, 0, Price::default(), replacement.remaining_size(), self.clock);
+        Self::notify(client.tx(), ExecutionReport::Replaced(details));
+        self.process_limit_order(replacement, tx_market_data);
+    }
+
+    /// Finds a resting order by id, as long as it belongs to the requesting client.
+    fn locate_order(&self, client: &Client, order_id: u64) -> Option<OrderHandle> {
+        let &handle = self.index.get(&order_id)?;
+        let limit_order = self.orders.get(handle)?;
+        (limit_order.client().owner() == client.owner()).then_some(handle)
+    }
+
+    fn add_order(&mut self, mut limit_order: LimitOrder) {
+        limit_order.replenish();
+        let order_id = limit_order.order_id();
+        let levels = match limit_order.side() {
+            MarketSide::Bid => &mut self.bids,
+            MarketSide::Ask => &mut self.asks,
+        };
+
+        self.changed_levels.push((limit_order.side(), limit_order.price()));
+        Self::order_event(&mut self.order_events, &limit_order, limit_order.visible_size(), OrderAction::Add);
+
+        let handle = levels
+            .entry(limit_order.price())
+            .or_default()
+            .push_back(&mut self.orders, limit_order);
+        self.index.insert(order_id, handle);
+    }
+
+    fn remove_order(&mut self, handle: OrderHandle) -> LimitOrder {
+        let resting = self.orders.get(handle).unwrap();
+        let price = resting.price();
+        self.changed_levels.push((resting.side(), price));
+        let levels = match resting.side() {
+            MarketSide::Bid => &mut self.bids,
+            MarketSide::Ask => &mut self.asks,
+        };
+
+        let level = levels.get_mut(&price).unwrap();
+        let limit_order = level.remove(&mut self.orders, handle);
+        if level.is_empty() {
+            levels.remove(&price);
+        }
+        self.index.remove(&limit_order.order_id());
+        Self::order_event(&mut self.order_events, &limit_order, 0, OrderAction::Delete);
+
+        limit_order
+    }
+
+    /// Rejects a request that refers to a resting order by its id.
+    fn reject_order_id(&mut self, client: &Client, order_id: u64, reason: RejectReason) {
+        let exec_id = self.next_exec_id;
+        self.next_exec_id += 1;
+        let details = ExecutionDetails::new(exec_id, order_id, String::new(), self.symbol().clone(), 0, Price::default(), 0, 0, self.clock);
+        Self::notify(client.tx(), ExecutionReport::Rejected(details, reason));
+    }
+
+    /// Builds the details of the next execution report for `order`, after its fill size
+    /// has been updated.
+    fn execution<O: Order + ?Sized>(next_exec_id: &mut u64, order: &O, last_qty: usize, last_price: Price, leaves_qty: usize, timestamp: DateTime<Utc>) -> ExecutionDetails {
+        let exec_id = *next_exec_id;
+        *next_exec_id += 1;
+        ExecutionDetails::new(exec_id, order.order_id(), order.client_order_id().clone(), order.symbol().clone(), last_qty, last_price, order.fill_size(), leaves_qty, timestamp)
+    }
+
+    fn fill_report<O: Order>(next_exec_id: &mut u64, order: &O, last_qty: usize, last_price: Price, timestamp: DateTime<Utc>) -> ExecutionReport {
+        let details = Self::execution(next_exec_id, order, last_qty, last_price, order.remaining_size(), timestamp);
+        if order.remaining_size() == 0 {
+            ExecutionReport::Fill(details)
+        } else {
+            ExecutionReport::PartialFill(details)
+        }
+    }
+
+    /// Sends synchronously so each client sees its reports in the order the book
+    /// produced them (e.g. a fill before the cancel of an IOC remainder).
+    fn notify(tx: mpsc::UnboundedSender<ClientMessage>, report: ExecutionReport) {
+        if let Err(e) = tx.send(report.into()) {
+            eprintln!("Error writing to channel: {e}");
+        }
+    }
+
+    /// Stamps the next sequence number of its feed on an update and sends it. Returns the
+    /// update as sent.
+    fn publish(&mut self, event: MarketDataEvent, tx: &mpsc::UnboundedSender<MarketData>) -> MarketData {
+        let update = match event.feed() {
+            Feed::Levels => {
+                let update = MarketData::new(self.symbol().clone(), self.published.seq() + 1, event, self.clock);
+                self.published.apply(&update).expect("feed updates are numbered in sequence");
+                update
+            },
+            Feed::Orders => {
+                self.order_feed_seq += 1;
+                MarketData::new(self.symbol().clone(), self.order_feed_seq, event, self.clock)
+            },
+        };
+        if let Err(e) = tx.send(update.clone()) {
+            eprintln!("Error writing market data on channel: {e}");
+        }
+        update
+    }
+
+    /// Brings both feeds up to date with the book.
+    fn publish_changes(&mut self, tx: &mpsc::UnboundedSender<MarketData>) {
+        for order_update in std::mem::take(&mut self.order_events) {
+            self.publish(MarketDataEvent::Order(order_update), tx);
+        }
+        self.publish_levels(tx);
+    }
+
+    /// Records an order feed event. Hidden orders never appear on the feed.
+    fn order_event(order_events: &mut Vec<OrderUpdate>, limit_order: &LimitOrder, quantity: usize, action: OrderAction) {
+        if !limit_order.is_hidden() {
+            order_events.push(OrderUpdate::new(limit_order.order_id(), limit_order.side(), limit_order.price(), quantity, action));
+        }
+    }
+
+    /// Publishes an add, change or delete for every level whose displayed quantity or
+    /// order count differs from what the feed last showed. Levels only touched by hidden
+    /// orders publish nothing.
+    fn publish_levels(&mut self, tx: &mpsc::UnboundedSender<MarketData>) {
+        for (side, price) in std::mem::take(&mut self.changed_levels) {
+            let levels = match side {
+                MarketSide::Bid => &self.bids,
+                MarketSide::Ask => &self.asks,
+            };
+            let current = self.aggregate(levels.get_key_value(&price).into_iter(), 1).pop();
+            let update = match (self.published.level(side, price), current) {
+                (None, Some(level)) => LevelUpdate::new(side, LevelAction::Add, level),
+                (Some(published), Some(level)) if published != level => LevelUpdate::new(side, LevelAction::Change, level),
+                (Some(_), None) => LevelUpdate::new(side, LevelAction::Delete, DepthLevel::new(price, 0, 0)),
+                _ => continue,
+            };
+            self.publish(MarketDataEvent::Level(update), tx);
+        }
     }
 
     fn increment(counter: Arc<AtomicU64>) {
         counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     }
 
-    fn get_best_bid(&mut self) -> Option<usize> {
-        if let Some((&price, _)) = self.bids.last_key_value() {
-            Some(price)
-        } else {
-            None
+    /// Reports the unfilled part of an order that is not going to rest in the book.
+    fn cancel_remainder<O: Order + ?Sized>(&mut self, order: &O, reason: CancelReason) {
+        let details = Self::execution(&mut self.next_exec_id, order, 0, Price::default(), 0, self.clock);
+        Self::notify(order.client().tx(), ExecutionReport::Canceled(details, reason));
+    }
+
+    fn get_best_bid(&self) -> Option<Price> {
+        self.bids.last_key_value().map(|(&price, _)| price)
+    }
+
+    fn get_best_ask(&self) -> Option<Price> {
+        self.asks.first_key_value().map(|(&price, _)| price)
+    }
+
+    /// Best price an order on `side` could trade against.
+    fn get_best_opposite(&self, side: MarketSide) -> Option<Price> {
+        match side {
+            MarketSide::Bid => self.get_best_ask(),
+            MarketSide::Ask => self.get_best_bid(),
         }
     }
 
-    fn get_best_ask(&mut self) -> Option<usize> {
-        if let Some((&price, _)) = self.asks.first_key_value() {
-            Some(price)
-        } else {
-            None
+    /// Returns the volatility band around the last traded price, or around the
+    /// instrument's reference price before the first trade.
+    fn volatility_band(&self) -> Option<(Price, Price)> {
+        let reference = self.last_price.unwrap_or(self.instrument.reference_price());
+        self.instrument.volatility_band(reference)
+    }
+
+    /// Returns true if a trade at `price` stays inside `band`.
+    fn within_band(band: Option<(Price, Price)>, price: Price) -> bool {
+        band.is_none_or(|(low, high)| low <= price && price <= high)
+    }
+
+    /// Tightens `limit_price` to the edge of the volatility band an order on `side` trades
+    /// towards.
+    fn band_limit(&self, side: MarketSide, limit_price: Option<Price>) -> Option<Price> {
+        let Some((low, high)) = self.volatility_band() else {
+            return limit_price;
+        };
+        match side {
+            MarketSide::Bid => Some(limit_price.map_or(high, |limit| limit.min(high))),
+            MarketSide::Ask => Some(limit_price.map_or(low, |limit| limit.max(low))),
         }
     }
 
-    fn match_order(&mut self, mut market_order: MarketOrder, tx_price: mpsc::UnboundedSender<usize>) {
-        loop {
-            let available_market_order_size = market_order.size() - market_order.fill_size();
+    /// Returns true if an order on `side` limited at `limit_price` may trade at `price`.
+    /// Market orders have no limit and cross at any price.
+    fn crosses(side: MarketSide, price: Price, limit_price: Option<Price>) -> bool {
+        match (side, limit_price) {
+            (_, None) => true,
+            (MarketSide::Bid, Some(limit)) => price <= limit,
+            (MarketSide::Ask, Some(limit)) => price >= limit,
+        }
+    }
 
-            if available_market_order_size == 0 {
+    /// Matches the incoming order against the opposite side of the book, best price first,
+    /// stopping once it is filled, the book is empty or the best opposite price is worse
+    /// than `limit_price`. Within a price level the allocation policy shares the order out
+    /// in whole lots, and fills go out in time priority. A trade that would print outside
+    /// the volatility band does not happen: the book goes into a volatility auction instead.
+    /// The band stays where it was when the order arrived for the whole sweep, the same band
+    /// a FOK order is checked against.
+    fn match_order<O: Order>(&mut self, order: &mut O, limit_price: Option<Price>, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        let lot_size = self.instrument.lot_size();
+        let band = self.volatility_band();
+        'matching: while order.remaining_size() > 0 {
+            let price = match self.get_best_opposite(order.side()) {
+                Some(price) if Self::crosses(order.side(), price, limit_price) => price,
+                _ => break,
+            };
+            if !Self::within_band(band, price) {
+                self.start_volatility_auction(tx_market_data);
                 break;
             }
 
-            let best_price = if market_order.side() == MarketSide::Ask {
-                self.get_best_bid()
+            let levels = if order.side() == MarketSide::Ask {
+                &self.bids
             } else {
-                self.get_best_ask()
+                &self.asks
             };
+            let (handles, sizes): (Vec<OrderHandle>, Vec<usize>) = levels[&price]
+                .iter(&self.orders)
+                .map(|(handle, limit_order)| (handle, limit_order.visible_size() / lot_size))
+                .unzip();
+            let allocations = self.allocation.allocate(order.remaining_size() / lot_size, &sizes);
+            if allocations.iter().all(|&lots| lots == 0) {
+                // Only possible off the lot grid, where nothing more can trade.
+                break;
+            }
 
-            if let Some(price) = best_price {
-                let orders_queue = if market_order.side() == MarketSide::Ask {
-                    self.bids.get_mut(&price).unwrap()
-                } else {
-                    self.asks.get_mut(&price).unwrap()
-                };
-                loop {
-                    let available_market_order_size = market_order.size() - market_order.fill_size();
-                    if available_market_order_size <= 0 {
-                        break;
-                    }
-                    if let Some(mut limit_order) = orders_queue.pop_front() {
-                        let available_limit_order_size = limit_order.size() - limit_order.fill_size();
-
-                        if available_limit_order_size < available_market_order_size {
-                            let msg_full_fill = format!("Order {} filled [{}/{}]", limit_order.order_id(), available_limit_order_size, limit_order.size());
-                            let msg_partial_fill = format!("Order {} filled [{}/{}] at {}", market_order.order_id(), available_limit_order_size, market_order.size(), limit_order.price());
-                            
-                            let limit_client_tx = limit_order.client().tx();
-                            let market_client_tx = market_order.client().tx();
-
-                            market_order.set_fill_size(market_order.fill_size() + available_limit_order_size);
-
-                            Self::notify(limit_client_tx, msg_full_fill);
-                            Self::notify(market_client_tx, msg_partial_fill);
-                            Self::send_price(tx_price.clone(), limit_order.price());
-
-                        } else if available_limit_order_size == available_market_order_size {
-                            let msg_market_client = format!("Order {} filled [{}/{}] at {}", market_order.order_id(), available_market_order_size, market_order.size(), limit_order.price());
-                            let msg_limit_client = format!("Order {} filled [{}/{}]", limit_order.order_id(), available_limit_order_size, limit_order.size());
-                            let limit_client_tx = limit_order.client().tx();
-                            let market_client_tx = market_order.client().tx();
-                            let price = limit_order.price();
-
-                            market_order.set_fill_size(market_order.fill_size() + available_market_order_size);
-                            
-                            Self::notify(limit_client_tx, msg_limit_client);
-                            Self::notify(market_client_tx, msg_market_client);
-                            Self::send_price(tx_price.clone(), price);
-
-                            break;
-                        } else {
-                            let msg_full_fill = format!("Order {} filled [{}/{}] at {}", market_order.order_id(), available_market_order_size, market_order.size(), limit_order.price());
-                            let msg_partial_fill = format!("Order {} filled [{}/{}]", limit_order.order_id(), available_market_order_size, limit_order.size());
-
-                            let limit_client_tx = limit_order.client().tx();
-                            let market_client_tx = market_order.client().tx();
-
-                            let price= limit_order.price();
-
-                            limit_order.set_fill_size(limit_order.fill_size() + available_market_order_size);
-                            market_order.set_fill_size(market_order.fill_size() + available_market_order_size);
-
-                            orders_queue.push_front(limit_order);
-
-                            Self::notify(limit_client_tx, msg_partial_fill);
-                            Self::notify(market_client_tx, msg_full_fill);
-                            Self::send_price(tx_price.clone(), price);
-
-                            break;
-                        }
-                    } else {
-                        if market_order.side() == MarketSide::Ask {
-                            println!("No bids at {price}");
-                        } else {
-                            println!("No asks at {price}");
-                        }
-                        break;
-                    }
+            for (handle, lots) in handles.into_iter().zip(allocations) {
+                if lots == 0 {
+                    continue;
                 }
-                let empty = orders_queue.is_empty();
-                if empty {
-                    match market_order.side() {
-                        MarketSide::Ask => {
-                            self.bids.remove(&price);
-                        },
-                        MarketSide::Bid => {
-                            self.asks.remove(&price);
-                        }
-                    }
+                if Self::is_self_trade(order, self.orders.get(handle).unwrap()) {
+                    // Share out again whatever is left once the self-trade is dealt with.
+                    self.prevent_self_trade(order, handle);
+                    continue 'matching;
                 }
+                self.fill_resting(order, handle, lots * lot_size, price, tx_market_data);
+            }
+        }
+    }
+
+    /// Executes `fill` of the incoming order against a resting one at `price`.
+    fn fill_resting<O: Order>(&mut self, order: &mut O, handle: OrderHandle, fill: usize, price: Price, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        let limit_order = self.orders.get_mut(handle).unwrap();
+
+        limit_order.set_fill_size(limit_order.fill_size() + fill);
+        order.set_fill_size(order.fill_size() + fill);
+        self.changed_levels.push((limit_order.side(), price));
+        Self::order_event(&mut self.order_events, limit_order, fill, OrderAction::Execute);
+
+        let limit_client_report = Self::fill_report(&mut self.next_exec_id, limit_order, fill, price, self.clock);
+        let order_client_report = Self::fill_report(&mut self.next_exec_id, order, fill, price, self.clock);
+
+        Self::notify(limit_order.client().tx(), limit_client_report);
+        Self::notify(order.client().tx(), order_client_report);
+        let trade = Trade::new(self.next_trade_id, self.instrument.symbol().clone(), price, fill, Some(order.side()), limit_order.order_id(), order.order_id(), self.clock);
+        self.next_trade_id += 1;
+        self.last_price = Some(price);
+
+        if limit_order.remaining_size() == 0 {
+            self.remove_order(handle);
+        } else if limit_order.visible_size() == 0 {
+            // Iceberg slice used up: show the next one at the back of the level.
+            let levels = match limit_order.side() {
+                MarketSide::Bid => &mut self.bids,
+                MarketSide::Ask => &mut self.asks,
+            };
+            let level = levels.get_mut(&price).unwrap();
+            let mut iceberg = level.remove(&mut self.orders, handle);
+            Self::order_event(&mut self.order_events, &iceberg, 0, OrderAction::Delete);
+            iceberg.replenish();
+            Self::order_event(&mut self.order_events, &iceberg, iceberg.visible_size(), OrderAction::Add);
+            let order_id = iceberg.order_id();
+            let handle = level.push_back(&mut self.orders, iceberg);
+            self.index.insert(order_id, handle);
+        }
+
+        // The print goes out first, then the level it took liquidity from.
+        self.publish(MarketDataEvent::Trade(trade), tx_market_data);
+        self.publish_changes(tx_market_data);
+    }
+
+    /// Equilibrium price of an uncross and the volume executable there: the price that
+    /// trades the most, then leaves the smallest surplus. Among prices still tied, a
+    /// surplus on the buy side at all of them picks the highest and one on the sell side
+    /// the lowest; otherwise the price closest to the last traded price (or the
+    /// instrument's reference price) wins, the lower one if two are equally close.
+    /// Hidden quantity and iceberg reserves take part in full.
+    fn equilibrium(&self) -> Option<(Price, usize)> {
+        let quantity = |level: &PriceLevel| level.iter(&self.orders).map(|(_, o)| o.remaining_size()).sum::<usize>();
+        let bids: Vec<(Price, usize)> = self.bids.iter().map(|(&price, level)| (price, quantity(level))).collect();
+        let asks: Vec<(Price, usize)> = self.asks.iter().map(|(&price, level)| (price, quantity(level))).collect();
+
+        let mut prices: Vec<Price> = bids.iter().chain(&asks).map(|&(price, _)| price).collect();
+        prices.sort_unstable();
+        prices.dedup();
+
+        // Candidates in ascending price order: (price, volume, buy surplus).
+        let mut candidates: Vec<(Price, usize, i128)> = Vec::new();
+        let (mut buy, mut sell) = (bids.iter().map(|&(_, qty)| qty).sum::<usize>(), 0);
+        let (mut next_bid, mut next_ask) = (0, 0);
+        for price in prices {
+            while next_bid < bids.len() && bids[next_bid].0 < price {
+                buy -= bids[next_bid].1;
+                next_bid += 1;
+            }
+            while next_ask < asks.len() && asks[next_ask].0 <= price {
+                sell += asks[next_ask].1;
+                next_ask += 1;
+            }
+            if buy.min(sell) > 0 {
+                candidates.push((price, buy.min(sell), buy as i128 - sell as i128));
+            }
+        }
+
+        let volume = candidates.iter().map(|c| c.1).max()?;
+        candidates.retain(|c| c.1 == volume);
+        let surplus = candidates.iter().map(|c| c.2.unsigned_abs()).min()?;
+        candidates.retain(|c| c.2.unsigned_abs() == surplus);
+
+        let price = if candidates.iter().all(|c| c.2 > 0) {
+            candidates.last()?.0
+        } else if candidates.iter().all(|c| c.2 < 0) {
+            candidates.first()?.0
+        } else {
+            let reference = self.last_price.unwrap_or(self.instrument.reference_price());
+            let distance = |price: Price| if price > reference { price.checked_sub(reference) } else { reference.checked_sub(price) };
+            candidates.iter().min_by_key(|c| distance(c.0))?.0
+        };
+        Some((price, volume))
+    }
+
+    /// Ends an auction call: the crossing orders trade at the equilibrium price, best price
+    /// first on each side and oldest first within a price, until the executable volume is
+    /// done. Self-trade prevention does not apply to the uncross.
+    fn uncross(&mut self, tx_market_data: &mpsc::UnboundedSender<MarketData>) {
+        let Some((price, volume)) = self.equilibrium() else {
+            return;
+        };
+        self.last_price = Some(price);
+
+        let mut left = volume;
+        while left > 0 {
+            let bid = self.bids.last_key_value().and_then(|(_, level)| level.front()).unwrap();
+            let ask = self.asks.first_key_value().and_then(|(_, level)| level.front()).unwrap();
+            let (bid_order, ask_order) = (self.orders.get(bid).unwrap(), self.orders.get(ask).unwrap());
+
+            let fill = left.min(bid_order.remaining_size()).min(ask_order.remaining_size());
+            let (maker_order_id, taker_order_id) = if bid_order.order_id() < ask_order.order_id() {
+                (bid_order.order_id(), ask_order.order_id())
             } else {
-                if market_order.side() == MarketSide::Ask {
-                    println!("There are no bids!");
-                } else {
-                    println!("There are no asks!"); 
+                (ask_order.order_id(), bid_order.order_id())
+            };
+            let trade = Trade::new(self.next_trade_id, self.symbol().clone(), price, fill, None, maker_order_id, taker_order_id, self.clock);
+            self.next_trade_id += 1;
+
+            self.auction_fill(bid, fill, price);
+            self.auction_fill(ask, fill, price);
+            left -= fill;
+
+            self.publish(MarketDataEvent::Trade(trade), tx_market_data);
+            self.publish_changes(tx_market_data);
+        }
+    }
+
+    /// Books an uncross execution on a resting order. An iceberg keeps its place and
+    /// shows its next slice if the fill went through the current one.
+    fn auction_fill(&mut self, handle: OrderHandle, fill: usize, price: Price) {
+        let limit_order = self.orders.get_mut(handle).unwrap();
+        limit_order.set_fill_size(limit_order.fill_size() + fill);
+        self.changed_levels.push((limit_order.side(), limit_order.price()));
+        Self::order_event(&mut self.order_events, limit_order, fill, OrderAction::Execute);
+
+        let report = Self::fill_report(&mut self.next_exec_id, limit_order, fill, price, self.clock);
+        Self::notify(limit_order.client().tx(), report);
+
+        if limit_order.remaining_size() == 0 {
+            self.remove_order(handle);
+        } else if limit_order.visible_size() == 0 {
+            limit_order.replenish();
+            Self::order_event(&mut self.order_events, limit_order, limit_order.visible_size(), OrderAction::Modify);
+        }
+    }
+
+    /// Publishes the indicative price and volume during an auction call, whenever they
+    /// change.
+    fn publish_indicative(&mut self, tx: &mpsc::UnboundedSender<MarketData>) {
+        if !self.phase.is_auction() {
+            return;
+        }
+        let indicative = self.indicative();
+        if self.indicative != Some(indicative) {
+            self.indicative = Some(indicative);
+            self.publish(MarketDataEvent::Indicative(indicative), tx);
+        }
+    }
+
+    /// Returns true if both orders carry the same account.
+    fn is_self_trade<O: Order>(order: &O, limit_order: &LimitOrder) -> bool {
+        order.account().is_some() && order.account() == limit_order.account()
+    }
+
+    /// Applies the incoming order's self-trade prevention mode against a resting order of
+    /// the same account, instead of trading the two.
+    fn prevent_self_trade<O: Order>(&mut self, order: &mut O, handle: OrderHandle) {
+        let limit_order = self.orders.get_mut(handle).unwrap();
+
+        let (cancel_order, cancel_resting) = match order.self_trade_prevention() {
+            SelfTradePrevention::CancelNewest => (true, false),
+            SelfTradePrevention::CancelOldest => (false, true),
+            SelfTradePrevention::CancelBoth => (true, true),
+            SelfTradePrevention::DecrementAndCancel => {
+                let qty = order.remaining_size().min(limit_order.remaining_size());
+                order.set_size(order.size() - qty);
+                limit_order.set_size(limit_order.size() - qty);
+                self.changed_levels.push((limit_order.side(), limit_order.price()));
+
+                if limit_order.remaining_size() > 0 {
+                    Self::order_event(&mut self.order_events, limit_order, limit_order.visible_size(), OrderAction::Modify);
+                    let details = Self::execution(&mut self.next_exec_id, limit_order, 0, Price::default(), limit_order.remaining_size(), self.clock);
+                    Self::notify(limit_order.client().tx(), ExecutionReport::Restated(details));
                 }
-                let unmatched_orders = market_order.size() - market_order.fill_size();
-                let msg = format!("Order {} unfilled [{}/{}]", market_order.order_id(), unmatched_orders, market_order.size());
-                tokio::spawn(async move {
-                    if let Err(e) = market_order.client().tx().send(msg).await {
-                        eprintln!("Error writing to channel: {e}");
-                    }
-                });
-                break;
+                if order.remaining_size() > 0 {
+                    let details = Self::execution(&mut self.next_exec_id, order, 0, Price::default(), order.remaining_size(), self.clock);
+                    Self::notify(order.client().tx(), ExecutionReport::Restated(details));
+                }
+                (order.remaining_size() == 0, limit_order.remaining_size() == 0)
+            },
+        };
+
+        if cancel_resting {
+            let limit_order = self.remove_order(handle);
+            self.cancel_remainder(&limit_order, CancelReason::SelfTrade);
+        }
+        if cancel_order {
+            self.cancel_remainder(order, CancelReason::SelfTrade);
+            // Nothing is left working, so the caller neither rests nor cancels it again.
+            order.set_size(order.fill_size());
+        }
+    }
+
+    fn aggregate<'a>(&self, levels: impl Iterator<Item = (&'a Price, &'a PriceLevel)>, count: usize) -> Vec<DepthLevel> {
+        levels
+            .filter_map(|(price, level)| {
+                let (quantity, order_count) = level
+                    .iter(&self.orders)
+                    .filter(|(_, o)| !o.is_hidden())
+                    .fold((0, 0), |(quantity, order_count), (_, o)| (quantity + o.visible_size(), order_count + 1));
+                (order_count > 0).then(|| DepthLevel::new(*price, quantity, order_count))
+            })
+            .take(count)
+            .collect()
+    }
+
+    /// Writes one line per price level, leaving out hidden orders and levels holding only
+    /// hidden orders.
+    fn fmt_levels<'a>(&self, f: &mut fmt::Formatter<'_>, levels: impl Iterator<Item = (&'a Price, &'a PriceLevel)>) -> fmt::Result {
+        for (price, level) in levels {
+            let mut shown = level.iter(&self.orders).filter(|(_, o)| !o.is_hidden()).peekable();
+            if shown.peek().is_none() {
+                continue;
             }
+            write!(f, "  {} -> ", price)?;
+            for (_, o) in shown {
+                write!(f, "{} ", o)?;
+            }
+            writeln!(f)?;
         }
+        Ok(())
     }
 }
 
 impl fmt::Display for OrderBook {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "OrderBook:")?;
+        writeln!(f, "OrderBook {}:", self.symbol())?;
         writeln!(f, "Bids:")?;
-        for (price, orders) in &self.bids {
-            write!(f, "  {} -> ", price)?;
-            for o in orders {
-                write!(f, "{} ", o)?;
+        self.fmt_levels(f, self.bids.iter())?;
+        writeln!(f, "Asks:")?;
+        self.fmt_levels(f, self.asks.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client_handler::Logins, market_data::{QueueBook, QueuedOrder}, orders::{StopLimitOrder, StopOrder}, testing::{client, login, reports}};
+
+    fn price(s: &str) -> Price {
+        s.parse().unwrap()
+    }
+
+    fn book() -> OrderBook {
+        OrderBook::new(Instrument::new("T".to_string(), price("0.01"), 1, 1, 1000, price("100.00"), 5000, 0))
+    }
+
+    fn limit(client: &Client, order_id: u64, side: MarketSide, price_str: &str, size: usize) -> LimitOrder {
+        let mut order = LimitOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), size, 0, side, price(price_str), client.clone(), format!("c{order_id}"));
+        order.set_order_id(order_id);
+        order
+    }
+
+    fn market(client: &Client, order_id: u64, side: MarketSide, size: usize) -> MarketOrder {
+        let mut order = MarketOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), size, 0, side, client.clone(), format!("c{order_id}"));
+        order.set_order_id(order_id);
+        order
+    }
+
+    fn submit(book: &mut OrderBook, order: impl Into<Orders>) {
+        let (tx_market_data, _rx_market_data) = mpsc::unbounded_channel();
+        book.handle_order(order.into(), tx_market_data, Arc::new(AtomicU64::new(0)));
+    }
+
+    /// Submits `order` and returns the order feed events it caused, as (order id, action,
+    /// quantity).
+    fn submit_orders_feed(book: &mut OrderBook, order: impl Into<Orders>) -> Vec<(u64, OrderAction, usize)> {
+        let (tx_market_data, mut rx_market_data) = mpsc::unbounded_channel();
+        book.handle_order(order.into(), tx_market_data, Arc::new(AtomicU64::new(0)));
+        let mut events = Vec::new();
+        while let Ok(update) = rx_market_data.try_recv() {
+            if let MarketDataEvent::Order(order_update) = update.event() {
+                events.push((order_update.order_id(), order_update.action(), order_update.quantity()));
             }
-            writeln!(f)?;
         }
-        writeln!(f, "Asks:")?;
-        for (price, orders) in &self.asks {
-            write!(f, "  {} -> ", price)?;
-            for o in orders {
-                write!(f, "{} ", o)?;
+        events
+    }
+
+    #[test]
+    fn fok_kills_rather_than_count_own_orders() {
+        let modes = [SelfTradePrevention::CancelNewest, SelfTradePrevention::CancelOldest, SelfTradePrevention::CancelBoth, SelfTradePrevention::DecrementAndCancel];
+        for mode in modes {
+            let mut book = book();
+            let (x, mut rx_x) = client(1);
+            let (y, _rx_y) = client(2);
+            submit(&mut book, limit(&x, 1, MarketSide::Ask, "100", 5).with_account("X".to_string()));
+            submit(&mut book, limit(&y, 2, MarketSide::Ask, "100", 5));
+            reports(&mut rx_x);
+
+            let fok = limit(&x, 3, MarketSide::Bid, "100", 10)
+                .with_time_in_force(TimeInForce::Fok)
+                .with_account("X".to_string())
+                .with_self_trade_prevention(mode);
+            submit(&mut book, fok);
+
+            let reports = reports(&mut rx_x);
+            assert!(matches!(reports.as_slice(), [ExecutionReport::New(_), ExecutionReport::Canceled(details, CancelReason::FillOrKill)] if details.cum_qty() == 0), "{mode:?}: {reports:?}");
+            assert_eq!(book.len(), 2, "{mode:?}");
+            assert_eq!(book.get_order(1).unwrap().remaining_size(), 5, "{mode:?}");
+            assert_eq!(book.get_order(2).unwrap().remaining_size(), 5, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn fok_fills_from_other_accounts() {
+        // Enough before the own order: it is never reached.
+        let mut book = book();
+        let (x, _rx_x) = client(1);
+        let (y, _rx_y) = client(2);
+        submit(&mut book, limit(&y, 1, MarketSide::Ask, "100", 10));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "100", 5).with_account("X".to_string()));
+        submit(&mut book, limit(&x, 3, MarketSide::Bid, "100", 10).with_time_in_force(TimeInForce::Fok).with_account("X".to_string()));
+        assert!(book.get_order(1).is_none());
+        assert_eq!(book.get_order(2).unwrap().remaining_size(), 5);
+
+        // Cancel oldest takes the own order out of the way and fills from the rest.
+        let mut book = self::book();
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100", 5).with_account("X".to_string()));
+        submit(&mut book, limit(&y, 2, MarketSide::Ask, "100", 10));
+        let fok = limit(&x, 3, MarketSide::Bid, "100", 10)
+            .with_time_in_force(TimeInForce::Fok)
+            .with_account("X".to_string())
+            .with_self_trade_prevention(SelfTradePrevention::CancelOldest);
+        submit(&mut book, fok);
+        assert!(book.is_empty());
+        assert_eq!(book.last_price(), Some(price("100")));
+    }
+
+    /// Book in the opening call, with the given reference price and resting orders as
+    /// (side, price, size), numbered from 1.
+    fn call(reference: &str, orders: &[(MarketSide, &str, usize)]) -> (OrderBook, Client) {
+        let instrument = Instrument::new("T".to_string(), price("0.01"), 1, 1, 1000, price(reference), 5000, 0);
+        let mut book = OrderBook::new(instrument);
+        let (tx_market_data, _rx_market_data) = mpsc::unbounded_channel();
+        book.set_phase(TradingPhase::OpeningAuction, DateTime::UNIX_EPOCH, &tx_market_data);
+
+        let (x, _rx_x) = client(1);
+        for (i, &(side, price, size)) in orders.iter().enumerate() {
+            submit(&mut book, limit(&x, i as u64 + 1, side, price, size));
+        }
+        (book, x)
+    }
+
+    fn indicative(book: &OrderBook) -> (Option<Price>, usize) {
+        let indicative = book.indicative();
+        (indicative.price(), indicative.volume())
+    }
+
+    #[test]
+    fn equilibrium_maximises_volume() {
+        // 15 trade at 100.00, against 5 at 99.00 and 10 at 101.00.
+        let (book, _) = call("100.00", &[(MarketSide::Bid, "101.00", 10), (MarketSide::Bid, "100.00", 5), (MarketSide::Ask, "99.00", 5), (MarketSide::Ask, "100.00", 10)]);
+        assert_eq!(indicative(&book), (Some(price("100.00")), 15));
+    }
+
+    #[test]
+    fn equilibrium_minimises_surplus() {
+        // 10 trade at both prices, leaving nothing at 100.00 and 5 to sell at 101.00.
+        let (book, _) = call("101.00", &[(MarketSide::Bid, "101.00", 10), (MarketSide::Ask, "100.00", 10), (MarketSide::Ask, "101.00", 5)]);
+        assert_eq!(indicative(&book), (Some(price("100.00")), 10));
+    }
+
+    #[test]
+    fn equilibrium_follows_market_pressure() {
+        // 5 left to buy at both 101.00 and 102.00: the highest wins.
+        let (book, _) = call("100.00", &[(MarketSide::Bid, "102.00", 15), (MarketSide::Ask, "100.00", 5), (MarketSide::Ask, "101.00", 5)]);
+        assert_eq!(indicative(&book), (Some(price("102.00")), 10));
+
+        // 5 left to sell at both 100.00 and 101.00: the lowest wins.
+        let (book, _) = call("102.00", &[(MarketSide::Ask, "100.00", 15), (MarketSide::Bid, "101.00", 5), (MarketSide::Bid, "102.00", 5)]);
+        assert_eq!(indicative(&book), (Some(price("100.00")), 10));
+    }
+
+    #[test]
+    fn equilibrium_closest_to_reference_price() {
+        // 10 trade with no surplus at both 100.00 and 102.00.
+        let orders = [(MarketSide::Bid, "102.00", 10), (MarketSide::Ask, "100.00", 10)];
+        assert_eq!(indicative(&call("99.00", &orders).0), (Some(price("100.00")), 10));
+        assert_eq!(indicative(&call("105.00", &orders).0), (Some(price("102.00")), 10));
+        // Equally close: the lower price.
+        assert_eq!(indicative(&call("101.00", &orders).0), (Some(price("100.00")), 10));
+    }
+
+    #[test]
+    fn equilibrium_prefers_last_price_to_reference() {
+        let (mut book, x) = call("100.00", &[]);
+        let (tx_market_data, _rx_market_data) = mpsc::unbounded_channel();
+        book.set_phase(TradingPhase::Continuous, DateTime::UNIX_EPOCH, &tx_market_data);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "102.00", 1));
+        submit(&mut book, limit(&x, 2, MarketSide::Bid, "102.00", 1));
+        assert_eq!(book.last_price(), Some(price("102.00")));
+
+        book.set_phase(TradingPhase::ClosingAuction, DateTime::UNIX_EPOCH, &tx_market_data);
+        submit(&mut book, limit(&x, 3, MarketSide::Bid, "102.00", 10));
+        submit(&mut book, limit(&x, 4, MarketSide::Ask, "100.00", 10));
+        assert_eq!(indicative(&book), (Some(price("102.00")), 10));
+    }
+
+    #[test]
+    fn equilibrium_none_without_cross() {
+        let (book, _) = call("100.00", &[(MarketSide::Bid, "99.00", 10), (MarketSide::Ask, "100.00", 10)]);
+        assert_eq!(indicative(&book), (None, 0));
+    }
+
+    #[test]
+    fn uncross_spans_several_levels() {
+        let (mut book, _) = call("100.00", &[
+            (MarketSide::Bid, "103.00", 5),
+            (MarketSide::Bid, "102.00", 5),
+            (MarketSide::Bid, "101.00", 5),
+            (MarketSide::Ask, "100.00", 4),
+            (MarketSide::Ask, "101.00", 6),
+            (MarketSide::Ask, "102.00", 8),
+        ]);
+        // 10 trade at 101.00 with 5 left to buy, at 102.00 with 8 left to sell.
+        assert_eq!(indicative(&book), (Some(price("101.00")), 10));
+
+        let (tx_market_data, mut rx_market_data) = mpsc::unbounded_channel();
+        book.set_phase(TradingPhase::Continuous, DateTime::UNIX_EPOCH, &tx_market_data);
+
+        let mut trades = Vec::new();
+        while let Ok(update) = rx_market_data.try_recv() {
+            if let MarketDataEvent::Trade(trade) = update.event() {
+                trades.push((trade.price(), trade.size(), trade.maker_order_id(), trade.taker_order_id(), trade.aggressor_side()));
             }
-            writeln!(f)?;
         }
-        Ok(())
+        // Best prices first on each side, all at the equilibrium price.
+        assert_eq!(trades, vec![
+            (price("101.00"), 4, 1, 4, None),
+            (price("101.00"), 1, 1, 5, None),
+            (price("101.00"), 5, 2, 5, None),
+        ]);
+        assert_eq!(book.last_price(), Some(price("101.00")));
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.get_order(3).unwrap().remaining_size(), 5);
+        assert_eq!(book.get_order(6).unwrap().remaining_size(), 8);
+        assert_eq!(book.phase(), TradingPhase::Continuous);
+    }
+
+    #[test]
+    fn cancel_anonymous_cancels_every_working_order_without_a_login() {
+        let mut book = book().with_halt_policy(HaltPolicy::Queue);
+        let (x, mut rx_x) = client(1);
+        let (y, mut rx_y) = login(&Logins::new(), "alice", 2);
+        submit(&mut book, limit(&x, 1, MarketSide::Bid, "99", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "101", 5));
+        submit(&mut book, StopLimitOrder::new(price("102"), limit(&x, 3, MarketSide::Bid, "103", 5)));
+        submit(&mut book, limit(&y, 5, MarketSide::Bid, "97", 5));
+        submit(&mut book, StopLimitOrder::new(price("102"), limit(&y, 6, MarketSide::Bid, "103", 5)));
+        submit(&mut book, ControlRequest::new(DateTime::UNIX_EPOCH, "T".to_string(), x.clone(), ControlAction::Halt));
+        submit(&mut book, limit(&x, 4, MarketSide::Bid, "98", 5));
+        submit(&mut book, AmendOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), x.clone(), 1, price("99"), 3));
+        submit(&mut book, limit(&y, 7, MarketSide::Bid, "96", 5));
+        submit(&mut book, AmendOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), y.clone(), 5, price("97"), 3));
+        assert_eq!(book.working_orders(), 7);
+        assert_eq!(book.anonymous_orders(), 4);
+        reports(&mut rx_x);
+        reports(&mut rx_y);
+
+        let (tx_market_data, _rx_market_data) = mpsc::unbounded_channel();
+        book.cancel_anonymous(CancelReason::Recovery, DateTime::UNIX_EPOCH, &tx_market_data);
+
+        let outcome: Vec<(u64, Option<CancelReason>)> = reports(&mut rx_x)
+            .iter()
+            .map(|report| match report {
+                ExecutionReport::Canceled(details, reason) => (details.order_id(), Some(*reason)),
+                ExecutionReport::Rejected(details, RejectReason::UnknownOrder) => (details.order_id(), None),
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(outcome, [(1, Some(CancelReason::Recovery)), (2, Some(CancelReason::Recovery)), (3, Some(CancelReason::Recovery)), (4, Some(CancelReason::Recovery)), (1, None)]);
+        assert!(reports(&mut rx_y).is_empty());
+        assert_eq!(book.working_orders(), 3);
+        assert_eq!(book.anonymous_orders(), 0);
+        assert_eq!(book.depth(usize::MAX).bids().iter().map(|level| level.price()).collect::<Vec<_>>(), [price("97")]);
+        assert!(book.depth(usize::MAX).asks().is_empty());
+    }
+
+    #[test]
+    fn working_lists_hidden_stops_and_held_orders() {
+        let mut book = book().with_halt_policy(HaltPolicy::Queue);
+        let (x, _rx_x) = client(1);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "101.00", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Bid, "99.00", 10).with_display_size(3));
+        submit(&mut book, limit(&x, 3, MarketSide::Bid, "99.00", 4).with_hidden());
+        submit(&mut book, limit(&x, 4, MarketSide::Bid, "100.00", 2).with_hidden());
+        submit(&mut book, StopOrder::new(price("98.00"), market(&x, 6, MarketSide::Ask, 5)));
+        submit(&mut book, StopLimitOrder::new(price("102.00"), limit(&x, 5, MarketSide::Bid, "103.00", 5)));
+        submit(&mut book, ControlRequest::new(DateTime::UNIX_EPOCH, "T".to_string(), x.clone(), ControlAction::Halt));
+        submit(&mut book, limit(&x, 7, MarketSide::Bid, "97.00", 1));
+        submit(&mut book, AmendOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), x.clone(), 2, price("99.00"), 8));
+
+        let lines: Vec<String> = book.working().iter().map(crate::protocol::serialize_working_order).collect();
+        assert_eq!(lines, [
+            "working state=resting order_id=4 owner=127.0.0.1:1 cum_qty=0 leaves_qty=2 visible_qty=2 request=buy limit T 100.00 2 c4 gtc hidden",
+            "working state=resting order_id=2 owner=127.0.0.1:1 cum_qty=0 leaves_qty=10 visible_qty=3 request=buy limit T 99.00 10 c2 gtc display=3",
+            "working state=resting order_id=3 owner=127.0.0.1:1 cum_qty=0 leaves_qty=4 visible_qty=4 request=buy limit T 99.00 4 c3 gtc hidden",
+            "working state=resting order_id=1 owner=127.0.0.1:1 cum_qty=0 leaves_qty=5 visible_qty=5 request=sell limit T 101.00 5 c1 gtc",
+            "working state=stop order_id=5 owner=127.0.0.1:1 cum_qty=0 leaves_qty=5 request=buy stoplimit T 102.00 103.00 5 c5 gtc",
+            "working state=stop order_id=6 owner=127.0.0.1:1 cum_qty=0 leaves_qty=5 request=sell stop T 98.00 5 c6 ioc",
+            "working state=held order_id=7 owner=127.0.0.1:1 cum_qty=0 leaves_qty=1 request=buy limit T 97.00 1 c7 gtc",
+            "working state=held order_id=2 owner=127.0.0.1:1 request=amend T 2 99.00 8",
+        ]);
+    }
+
+    #[test]
+    fn protection_band_past_largest_price_is_capped() {
+        let mut book = book();
+        let (x, _rx_x) = client(1);
+        let mut ask = LimitOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), 5, 0, MarketSide::Ask, Price::new(u64::MAX - 1, 2), x.clone(), "a".to_string());
+        ask.set_order_id(1);
+        submit(&mut book, ask);
+
+        let market = MarketOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), 5, 0, MarketSide::Bid, x, "b".to_string())
+            .with_protection(PriceProtection::BasisPoints(100));
+        assert_eq!(book.protection_price(&market), Some(Price::new(u64::MAX, 2)));
+    }
+
+    #[test]
+    fn post_only_repriced_within_band() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        submit(&mut book, limit(&x, 1, MarketSide::Bid, "120.00", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "119.00", 5).with_post_only(PostOnly::Reprice));
+        assert_eq!(book.get_order(2).map(LimitOrder::price), Some(price("120.01")));
+        assert!(matches!(reports(&mut rx_x).as_slice(), [ExecutionReport::New(_), ExecutionReport::New(_)]));
+    }
+
+    #[test]
+    fn post_only_repriced_out_of_band_rejected() {
+        // The band runs from 50.00 to 150.00.
+        for (resting, resting_price, side, limit_price) in [(MarketSide::Bid, "150.00", MarketSide::Ask, "149.00"), (MarketSide::Ask, "50.00", MarketSide::Bid, "51.00")] {
+            let mut book = book();
+            let (x, mut rx_x) = client(1);
+            submit(&mut book, limit(&x, 1, resting, resting_price, 5));
+            reports(&mut rx_x);
+
+            submit(&mut book, limit(&x, 2, side, limit_price, 5).with_post_only(PostOnly::Reprice));
+            let reports = reports(&mut rx_x);
+            assert!(matches!(reports.as_slice(), [ExecutionReport::Rejected(_, RejectReason::PriceOutOfBand)]), "{reports:?}");
+            assert_eq!(book.len(), 1);
+        }
+    }
+
+    #[test]
+    fn sweep_stops_at_band_around_arrival_price() {
+        // A 10% band around 100.00: the fills at 109.00 must not move it out to 119.00.
+        let mut book = OrderBook::new(Instrument::new("T".to_string(), price("0.01"), 1, 1, 1000, price("100.00"), 5000, 1000));
+        let (x, _rx_x) = client(1);
+        let (y, mut rx_y) = client(2);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "109.00", 5));
+        submit(&mut book, limit(&x, 3, MarketSide::Ask, "119.00", 5));
+        submit(&mut book, limit(&x, 4, MarketSide::Ask, "129.00", 5));
+
+        submit(&mut book, limit(&y, 5, MarketSide::Bid, "130.00", 20));
+        assert_eq!(book.last_price(), Some(price("109.00")));
+        assert_eq!(book.phase(), TradingPhase::VolatilityAuction);
+        assert_eq!(book.get_order(3).unwrap().remaining_size(), 5);
+        assert_eq!(book.get_order(5).unwrap().remaining_size(), 10);
+        let fills = reports(&mut rx_y).into_iter().filter(|report| matches!(report, ExecutionReport::PartialFill(_))).count();
+        assert_eq!(fills, 2);
+    }
+
+    fn control(client: &Client, minutes: i64, action: ControlAction) -> ControlRequest {
+        ControlRequest::new(DateTime::UNIX_EPOCH + TimeDelta::minutes(minutes), "T".to_string(), client.clone(), action)
+    }
+
+    /// Book that went into a five minute volatility auction at the epoch, with a bid for
+    /// 5 at 120.00 crossing an ask for 5 at 115.00.
+    fn volatility_auction() -> (OrderBook, Client) {
+        let mut book = OrderBook::new(Instrument::new("T".to_string(), price("0.01"), 1, 1, 1000, price("100.00"), 5000, 1000));
+        let (x, _rx_x) = client(1);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "115.00", 5));
+        submit(&mut book, limit(&x, 3, MarketSide::Bid, "120.00", 10));
+        assert_eq!(book.phase(), TradingPhase::VolatilityAuction);
+        (book, x)
+    }
+
+    #[test]
+    fn resume_carries_on_volatility_auction() {
+        let (mut book, x) = volatility_auction();
+        submit(&mut book, control(&x, 1, ControlAction::Halt));
+        assert_eq!(book.volatility_auction_end(), None);
+        submit(&mut book, control(&x, 2, ControlAction::Resume));
+        assert_eq!(book.phase(), TradingPhase::VolatilityAuction);
+        assert_eq!(book.volatility_auction_end(), Some(DateTime::UNIX_EPOCH + TimeDelta::minutes(5)));
+        assert_eq!(book.len(), 2);
+
+        let (tx_market_data, _rx_market_data) = mpsc::unbounded_channel();
+        book.end_volatility_auction(DateTime::UNIX_EPOCH + TimeDelta::minutes(5), &tx_market_data);
+        assert_eq!(book.phase(), TradingPhase::Continuous);
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn resume_after_volatility_auction_time_uncrosses() {
+        let (mut book, x) = volatility_auction();
+        submit(&mut book, control(&x, 1, ControlAction::Halt));
+        submit(&mut book, control(&x, 6, ControlAction::Resume));
+        assert_eq!(book.phase(), TradingPhase::Continuous);
+        assert!(book.is_empty());
+    }
+
+    /// Fills among `reports` as (order id, last qty, last price, leaves qty).
+    fn fills(reports: &[ExecutionReport]) -> Vec<(u64, usize, Price, usize)> {
+        reports
+            .iter()
+            .filter(|report| matches!(report, ExecutionReport::PartialFill(_) | ExecutionReport::Fill(_)))
+            .map(|report| {
+                let details = report.details();
+                (details.order_id(), details.last_qty(), details.last_price(), details.leaves_qty())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn crossing_limit_order_trades_at_resting_prices() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        let (y, mut rx_y) = client(2);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "101.00", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "100.00", 5));
+        submit(&mut book, limit(&x, 3, MarketSide::Ask, "102.00", 5));
+        reports(&mut rx_x);
+
+        // Best price first, and each fill at the resting order's price.
+        submit(&mut book, limit(&y, 4, MarketSide::Bid, "101.50", 12));
+        assert_eq!(fills(&reports(&mut rx_y)), [(4, 5, price("100.00"), 7), (4, 5, price("101.00"), 2)]);
+        assert_eq!(fills(&reports(&mut rx_x)), [(2, 5, price("100.00"), 0), (1, 5, price("101.00"), 0)]);
+        assert_eq!(book.last_price(), Some(price("101.00")));
+
+        // What does not cross rests at its limit.
+        assert_eq!(book.get_order(4).map(|order| (order.price(), order.remaining_size())), Some((price("101.50"), 2)));
+        assert_eq!(book.len(), 2);
+    }
+
+    #[test]
+    fn same_price_fills_in_time_priority() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        let (y, _rx_y) = client(2);
+        for order_id in 1..=3 {
+            submit(&mut book, limit(&x, order_id, MarketSide::Bid, "100.00", 4));
+        }
+        reports(&mut rx_x);
+
+        submit(&mut book, limit(&y, 4, MarketSide::Ask, "99.00", 6));
+        assert_eq!(fills(&reports(&mut rx_x)), [(1, 4, price("100.00"), 0), (2, 2, price("100.00"), 2)]);
+        assert!(book.get_order(1).is_none());
+        assert_eq!(book.get_order(2).unwrap().remaining_size(), 2);
+        assert_eq!(book.get_order(3).unwrap().remaining_size(), 4);
+        assert!(book.get_order(4).is_none());
+    }
+
+    #[test]
+    fn non_crossing_limit_order_rests() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.01", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Bid, "100.00", 5));
+        assert!(matches!(reports(&mut rx_x).as_slice(), [ExecutionReport::New(_), ExecutionReport::New(_)]));
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.last_price(), None);
+    }
+
+    fn cancel(client: &Client, order_id: u64) -> CancelOrder {
+        CancelOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), client.clone(), order_id)
+    }
+
+    #[test]
+    fn cancel_removes_order_and_empty_level() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        submit(&mut book, limit(&x, 1, MarketSide::Bid, "100.00", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Bid, "99.00", 5));
+        reports(&mut rx_x);
+
+        submit(&mut book, cancel(&x, 1));
+        let reports = reports(&mut rx_x);
+        assert!(matches!(reports.as_slice(), [ExecutionReport::Canceled(details, CancelReason::Requested)] if details.order_id() == 1 && details.leaves_qty() == 0), "{reports:?}");
+        assert!(book.get_order(1).is_none());
+        let depth = book.depth(usize::MAX);
+        assert_eq!(depth.bids().iter().map(DepthLevel::price).collect::<Vec<_>>(), [price("99.00")]);
+    }
+
+    #[test]
+    fn cancel_refused_unless_owned_and_working() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        let (y, mut rx_y) = client(2);
+        submit(&mut book, limit(&x, 1, MarketSide::Bid, "100.00", 5));
+        reports(&mut rx_x);
+
+        // Someone else's order, an unknown id, and an order already canceled.
+        submit(&mut book, cancel(&y, 1));
+        submit(&mut book, cancel(&x, 2));
+        submit(&mut book, cancel(&x, 1));
+        submit(&mut book, cancel(&x, 1));
+        let rejects = |reports: Vec<ExecutionReport>| reports
+            .into_iter()
+            .filter(|report| matches!(report, ExecutionReport::Rejected(_, RejectReason::UnknownOrder)))
+            .map(|report| report.details().order_id())
+            .collect::<Vec<_>>();
+        assert_eq!(rejects(reports(&mut rx_y)), [1]);
+        assert_eq!(rejects(reports(&mut rx_x)), [2, 1]);
+        assert!(book.is_empty());
+    }
+
+    fn amend(client: &Client, order_id: u64, price_str: &str, size: usize) -> AmendOrder {
+        AmendOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), client.clone(), order_id, price(price_str), size)
+    }
+
+    /// Order ids of the asks filled, in order, by a market buy for `size`.
+    fn fill_order(book: &mut OrderBook, maker: &mut mpsc::UnboundedReceiver<ClientMessage>, size: usize) -> Vec<u64> {
+        let (y, _rx_y) = client(9);
+        submit(book, market(&y, 99, MarketSide::Bid, size));
+        fills(&reports(maker)).into_iter().map(|(order_id, ..)| order_id).collect()
+    }
+
+    #[test]
+    fn amend_down_keeps_priority() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "100.00", 5));
+        submit(&mut book, amend(&x, 1, "100.00", 3));
+        let reports = reports(&mut rx_x);
+        assert!(matches!(&reports[2..], [ExecutionReport::Replaced(details)] if details.leaves_qty() == 3), "{reports:?}");
+
+        assert_eq!(fill_order(&mut book, &mut rx_x, 3), [1]);
+    }
+
+    #[test]
+    fn amend_up_or_reprice_loses_priority() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "100.00", 5));
+        submit(&mut book, amend(&x, 1, "100.00", 6));
+        assert_eq!(fill_order(&mut book, &mut rx_x, 5), [2]);
+
+        let mut book = self::book();
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 5));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "100.00", 5));
+        submit(&mut book, amend(&x, 1, "101.00", 5));
+        submit(&mut book, amend(&x, 1, "100.00", 5));
+        assert_eq!(fill_order(&mut book, &mut rx_x, 5), [2]);
+    }
+
+    #[test]
+    fn amend_to_crossing_price_matches() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        let (y, mut rx_y) = client(2);
+        submit(&mut book, limit(&x, 1, MarketSide::Bid, "99.00", 5));
+        submit(&mut book, limit(&y, 2, MarketSide::Ask, "100.00", 3));
+        submit(&mut book, amend(&x, 1, "100.00", 5));
+
+        assert_eq!(fills(&reports(&mut rx_x)), [(1, 3, price("100.00"), 2)]);
+        assert_eq!(fills(&reports(&mut rx_y)), [(2, 3, price("100.00"), 0)]);
+        assert_eq!(book.get_order(1).unwrap().remaining_size(), 2);
+    }
+
+    #[test]
+    fn amend_below_filled_size_rejected() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 5));
+        fill_order(&mut book, &mut rx_x, 3);
+
+        submit(&mut book, amend(&x, 1, "100.00", 3));
+        let reports = reports(&mut rx_x);
+        assert!(matches!(reports.as_slice(), [ExecutionReport::Rejected(details, RejectReason::TooLateToAmend)] if details.leaves_qty() == 2), "{reports:?}");
+        assert_eq!(book.get_order(1).unwrap().remaining_size(), 2);
+    }
+
+    /// Checks that the order index, the arena and the price levels agree.
+    fn check_bookkeeping(book: &OrderBook) {
+        let mut resting = 0;
+        for (side, levels) in [(MarketSide::Bid, &book.bids), (MarketSide::Ask, &book.asks)] {
+            for (&price, level) in levels {
+                assert!(!level.is_empty(), "empty level at {price}");
+                assert_eq!(level.iter(&book.orders).count(), level.len());
+                for (handle, order) in level.iter(&book.orders) {
+                    assert_eq!((order.side(), order.price()), (side, price));
+                    assert_eq!(book.index.get(&order.order_id()), Some(&handle));
+                }
+                resting += level.len();
+            }
+        }
+        assert_eq!(book.len(), resting);
+    }
+
+    #[test]
+    fn index_follows_removals() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        for order_id in 1..=4 {
+            submit(&mut book, limit(&x, order_id, MarketSide::Ask, "100.00", 5));
+        }
+        submit(&mut book, limit(&x, 5, MarketSide::Ask, "101.00", 5));
+
+        // Take out the head, the middle and the tail of a level, then refill the freed slots.
+        fill_order(&mut book, &mut rx_x, 5);
+        submit(&mut book, cancel(&x, 3));
+        submit(&mut book, cancel(&x, 4));
+        check_bookkeeping(&book);
+        assert_eq!(book.get_order(2).unwrap().remaining_size(), 5);
+        for order_id in [1, 3, 4] {
+            assert!(book.get_order(order_id).is_none());
+        }
+
+        for order_id in 6..=8 {
+            submit(&mut book, limit(&x, order_id, MarketSide::Ask, "100.00", 5));
+        }
+        submit(&mut book, amend(&x, 5, "100.00", 5));
+        check_bookkeeping(&book);
+        assert_eq!(book.len(), 5);
+        assert_eq!(fill_order(&mut book, &mut rx_x, 25), [2, 6, 7, 8, 5]);
+        check_bookkeeping(&book);
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn ioc_cancels_what_does_not_fill() {
+        let mut book = book();
+        let (x, _rx_x) = client(1);
+        let (y, mut rx_y) = client(2);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 3));
+
+        submit(&mut book, limit(&y, 2, MarketSide::Bid, "100.00", 5).with_time_in_force(TimeInForce::Ioc));
+        submit(&mut book, limit(&y, 3, MarketSide::Bid, "100.00", 5).with_time_in_force(TimeInForce::Ioc));
+        let reports = reports(&mut rx_y);
+        assert!(matches!(reports.as_slice(), [
+            ExecutionReport::New(_),
+            ExecutionReport::PartialFill(_),
+            ExecutionReport::Canceled(first, CancelReason::ImmediateOrCancel),
+            ExecutionReport::New(_),
+            ExecutionReport::Canceled(second, CancelReason::ImmediateOrCancel),
+        ] if (first.cum_qty(), first.leaves_qty()) == (3, 0) && (second.cum_qty(), second.leaves_qty()) == (0, 0)), "{reports:?}");
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn day_orders_expire_at_session_end() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        submit(&mut book, limit(&x, 3, MarketSide::Bid, "99.00", 5).with_time_in_force(TimeInForce::Day));
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "101.00", 5).with_time_in_force(TimeInForce::Day));
+        submit(&mut book, limit(&x, 2, MarketSide::Bid, "98.00", 5));
+        submit(&mut book, StopLimitOrder::new(price("102.00"), limit(&x, 4, MarketSide::Bid, "103.00", 5).with_time_in_force(TimeInForce::Day)));
+        submit(&mut book, StopLimitOrder::new(price("102.00"), limit(&x, 5, MarketSide::Bid, "103.00", 5)));
+        reports(&mut rx_x);
+
+        let (tx_market_data, _rx_market_data) = mpsc::unbounded_channel();
+        book.expire_day_orders(DateTime::UNIX_EPOCH + TimeDelta::hours(22), &tx_market_data);
+        let expired: Vec<u64> = reports(&mut rx_x)
+            .iter()
+            .map(|report| match report {
+                ExecutionReport::Expired(details) => details.order_id(),
+                other => panic!("{other:?}"),
+            })
+            .collect();
+        assert_eq!(expired, [1, 3, 4]);
+        assert_eq!(book.len(), 1);
+        assert!(book.get_order(2).is_some());
+        assert_eq!(book.working_orders(), 2);
+    }
+
+    #[test]
+    fn stop_waits_for_trigger_price() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        let (y, mut rx_y) = client(2);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 1));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "101.00", 1));
+        submit(&mut book, limit(&x, 3, MarketSide::Ask, "102.00", 5));
+        submit(&mut book, StopOrder::new(price("101.00"), market(&y, 4, MarketSide::Bid, 2)));
+        submit(&mut book, StopLimitOrder::new(price("101.00"), limit(&y, 5, MarketSide::Bid, "101.50", 2)));
+
+        // A print below the trigger leaves both stops waiting.
+        fill_order(&mut book, &mut rx_x, 1);
+        assert_eq!(book.working_orders(), 4);
+        reports(&mut rx_y);
+
+        // At the trigger the stop enters as a market order and the stop-limit as a limit
+        // order, which rests since nothing is left at its price.
+        fill_order(&mut book, &mut rx_x, 1);
+        assert_eq!(fills(&reports(&mut rx_y)), [(4, 2, price("102.00"), 0)]);
+        assert_eq!(book.last_price(), Some(price("102.00")));
+        assert_eq!(book.get_order(5).map(|order| (order.price(), order.remaining_size())), Some((price("101.50"), 2)));
+        assert_eq!(book.working_orders(), 2);
+    }
+
+    #[test]
+    fn stops_cascade_in_trigger_order() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        let (y, mut rx_y) = client(2);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 1));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "101.00", 3));
+        submit(&mut book, limit(&x, 3, MarketSide::Ask, "102.00", 1));
+        submit(&mut book, limit(&x, 4, MarketSide::Ask, "103.00", 5));
+        // Fired together by a print at 100.00: lowest trigger first, then by arrival.
+        submit(&mut book, StopOrder::new(price("100.00"), market(&y, 5, MarketSide::Bid, 1)));
+        submit(&mut book, StopOrder::new(price("99.00"), market(&y, 6, MarketSide::Bid, 1)));
+        submit(&mut book, StopOrder::new(price("100.00"), market(&y, 7, MarketSide::Bid, 1)));
+        // Fired in the next wave, by the stops' own print at 101.00.
+        submit(&mut book, StopOrder::new(price("101.00"), market(&y, 8, MarketSide::Bid, 2)));
+        // Out of reach until 102.00 prints, which the order above does.
+        submit(&mut book, StopOrder::new(price("103.00"), market(&y, 9, MarketSide::Bid, 1)));
+        submit(&mut book, StopOrder::new(price("102.00"), market(&y, 10, MarketSide::Bid, 1)));
+        reports(&mut rx_y);
+
+        fill_order(&mut book, &mut rx_x, 1);
+        assert_eq!(fills(&reports(&mut rx_y)), [
+            (6, 1, price("101.00"), 0),
+            (5, 1, price("101.00"), 0),
+            (7, 1, price("101.00"), 0),
+            (8, 1, price("102.00"), 1),
+            (8, 1, price("103.00"), 0),
+            (10, 1, price("103.00"), 0),
+            (9, 1, price("103.00"), 0),
+        ]);
+        assert_eq!(book.working_orders(), 1);
+        assert_eq!(book.get_order(4).unwrap().remaining_size(), 2);
+    }
+
+    #[test]
+    fn iceberg_slice_replenishes_at_back_of_level() {
+        let mut book = book();
+        let (x, mut rx_x) = client(1);
+        let (y, _rx_y) = client(2);
+        submit(&mut book, limit(&x, 1, MarketSide::Ask, "100.00", 5).with_display_size(2));
+        submit(&mut book, limit(&x, 2, MarketSide::Ask, "100.00", 2));
+        reports(&mut rx_x);
+        let level = |book: &OrderBook| {
+            let depth = book.depth(1);
+            depth.asks().first().map(|level| (level.quantity(), level.order_count()))
+        };
+        assert_eq!(level(&book), Some((4, 2)));
+
+        // The slice is used up: the next one is shown behind order 2, and only it is shown.
+        let events = submit_orders_feed(&mut book, market(&y, 3, MarketSide::Bid, 2));
+        assert_eq!(events, [(1, OrderAction::Execute, 2), (1, OrderAction::Delete, 0), (1, OrderAction::Add, 2)]);
+        assert_eq!(fills(&reports(&mut rx_x)), [(1, 2, price("100.00"), 3)]);
+        assert_eq!(level(&book), Some((4, 2)));
+
+        submit(&mut book, market(&y, 4, MarketSide::Bid, 3));
+        assert_eq!(fills(&reports(&mut rx_x)), [(2, 2, price("100.00"), 0), (1, 1, price("100.00"), 2)]);
+        assert_eq!(level(&book), Some((1, 1)));
+
+        // The last slice is whatever is left.
+        let events = submit_orders_feed(&mut book, market(&y, 5, MarketSide::Bid, 2));
+        assert_eq!(events, [
+            (1, OrderAction::Execute, 1),
+            (1, OrderAction::Delete, 0),
+            (1, OrderAction::Add, 1),
+            (1, OrderAction::Execute, 1),
+            (1, OrderAction::Delete, 0),
+        ]);
+        assert_eq!(fills(&reports(&mut rx_x)), [(1, 1, price("100.00"), 1), (1, 1, price("100.00"), 0)]);
+        assert!(book.is_empty());
+    }
+
+    #[test]
+    fn hidden_orders_stay_off_the_feeds() {
+        let mut book = book();
+        let (x, _rx_x) = client(1);
+        let (y, _rx_y) = client(2);
+        let (tx_market_data, mut rx_market_data) = mpsc::unbounded_channel();
+        let counter = Arc::new(AtomicU64::new(0));
+        let orders = [
+            limit(&x, 1, MarketSide::Ask, "100.00", 5).with_hidden(),
+            limit(&x, 2, MarketSide::Ask, "100.00", 4),
+            limit(&x, 3, MarketSide::Ask, "101.00", 3).with_hidden(),
+            limit(&x, 4, MarketSide::Bid, "99.00", 2),
+        ];
+        for order in orders {
+            book.handle_order(order.into(), tx_market_data.clone(), counter.clone());
+        }
+        book.handle_order(market(&y, 5, MarketSide::Bid, 6).into(), tx_market_data.clone(), counter.clone());
+
+        let mut levels = LevelBook::new("T".to_string());
+        let mut queues = QueueBook::new("T".to_string());
+        while let Ok(update) = rx_market_data.try_recv() {
+            if let MarketDataEvent::Order(order_update) = update.event() {
+                assert!(![1, 3].contains(&order_update.order_id()), "{update:?}");
+            }
+            match update.feed() {
+                Feed::Levels => levels.apply(&update).unwrap(),
+                Feed::Orders => queues.apply(&update).unwrap(),
+            }
+        }
+
+        // The hidden order 1 keeps its time priority and fills first.
+        assert!(book.get_order(1).is_none());
+        let snapshot = queues.snapshot();
+        let queue = |orders: &[QueuedOrder]| orders.iter().map(|order| (order.order_id(), order.quantity())).collect::<Vec<_>>();
+        assert_eq!(queue(snapshot.asks()), [(2, 3)]);
+        assert_eq!(queue(snapshot.bids()), [(4, 2)]);
+        let depth = book.depth(usize::MAX);
+        assert_eq!(depth.asks(), [DepthLevel::new(price("100.00"), 3, 1)]);
+        assert_eq!(levels.snapshot().asks(), depth.asks());
+        assert_eq!(levels.snapshot().bids(), depth.bids());
     }
 }
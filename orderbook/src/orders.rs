@@ -2,7 +2,7 @@ use core::fmt;
 
 use chrono::{DateTime, Utc};
 
-use crate::client_handler::Client;
+use crate::{client_handler::Client, price::Price};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MarketSide {
@@ -10,124 +10,706 @@ pub enum MarketSide {
     Ask,
 }
 
+/// How long an order stays working before the book cancels or expires what is left of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeInForce {
+    /// Good till canceled: the remainder rests until filled or canceled.
+    Gtc,
+    /// Rests like GTC but expires at the end of the trading session.
+    Day,
+    /// Immediate or cancel: fills what it can on arrival and cancels the rest.
+    Ioc,
+    /// Fill or kill: fills completely on arrival or not at all.
+    Fok,
+}
+
+/// Bound on how far a market order may sweep the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceProtection {
+    /// Worst price the order may trade at.
+    Limit(Price),
+    /// Maximum distance from the best opposite price at arrival, in basis points.
+    BasisPoints(usize),
+}
+
+/// What happens to a post-only order that would take liquidity on arrival.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostOnly {
+    /// Turn the order away.
+    Reject,
+    /// Move it one tick away from the best opposite price so it rests instead.
+    Reprice,
+}
+
+/// What the book does when an incoming order would trade against a resting order of
+/// the same account. The incoming order's mode applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SelfTradePrevention {
+    /// Cancel the rest of the incoming order.
+    #[default]
+    CancelNewest,
+    /// Cancel the resting order and keep matching.
+    CancelOldest,
+    /// Cancel both orders.
+    CancelBoth,
+    /// Reduce both orders by the smaller remaining size and cancel whichever is left
+    /// with nothing.
+    DecrementAndCancel,
+}
+
+pub trait Order {
+    fn timestamp(&self) -> &DateTime<Utc>;
+
+    /// Instrument the order trades, which picks the book it goes to.
+    fn symbol(&self) -> &String;
+
+    fn size(&self) -> usize;
+
+    fn set_size(&mut self, size: usize);
+
+    fn fill_size(&self) -> usize;
+
+    fn set_fill_size(&mut self, fill_size: usize);
+
+    fn side(&self) -> MarketSide;
+
+    fn client(&self) -> &Client;
+
+    /// Exchange-assigned id, unique across all books. Zero until the registry routes the order.
+    fn order_id(&self) -> u64;
+
+    fn set_order_id(&mut self, order_id: u64);
+
+    /// Id chosen by the client, unique within its session.
+    fn client_order_id(&self) -> &String;
+
+    fn time_in_force(&self) -> TimeInForce;
+
+    /// Account or trader the order belongs to, used for self-trade prevention.
+    fn account(&self) -> Option<&String>;
+
+    fn self_trade_prevention(&self) -> SelfTradePrevention;
+
+    fn remaining_size(&self) -> usize {
+        self.size() - self.fill_size()
+    }
+}
+
 #[derive(Debug)]
 pub struct MarketOrder {
     timestamp: DateTime<Utc>,
+    symbol: String,
     size: usize,
     fill_size: usize,
     side: MarketSide,
     client: Client,
-    order_id: String,
+    order_id: u64,
+    client_order_id: String,
+    time_in_force: TimeInForce,
+    protection: Option<PriceProtection>,
+    account: Option<String>,
+    self_trade_prevention: SelfTradePrevention,
 }
 
 impl MarketOrder {
-    pub fn new(timestamp: DateTime<Utc>, size: usize, fill_size: usize, side: MarketSide, client: Client, order_id: String) -> Self {
+    pub fn new(timestamp: DateTime<Utc>, symbol: String, size: usize, fill_size: usize, side: MarketSide, client: Client, client_order_id: String) -> Self {
         MarketOrder {
             timestamp,
+            symbol,
             size,
             fill_size,
             side,
             client,
-            order_id,
+            order_id: 0,
+            client_order_id,
+            time_in_force: TimeInForce::Ioc,
+            protection: None,
+            account: None,
+            self_trade_prevention: SelfTradePrevention::default(),
         }
     }
 
-    pub fn timestamp(&self) -> &DateTime<Utc> {
+    /// Market orders never rest, so only `Ioc` (the default) and `Fok` are meaningful.
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn with_protection(mut self, protection: PriceProtection) -> Self {
+        self.protection = Some(protection);
+        self
+    }
+
+    pub fn protection(&self) -> Option<PriceProtection> {
+        self.protection
+    }
+
+    pub fn with_account(mut self, account: String) -> Self {
+        self.account = Some(account);
+        self
+    }
+
+    pub fn with_self_trade_prevention(mut self, self_trade_prevention: SelfTradePrevention) -> Self {
+        self.self_trade_prevention = self_trade_prevention;
+        self
+    }
+}
+
+impl Order for MarketOrder {
+    fn timestamp(&self) -> &DateTime<Utc> {
         &self.timestamp
     }
 
-    pub fn size(&self) -> usize {
+    fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    fn size(&self) -> usize {
         self.size
     }
 
-    pub fn fill_size(&self) -> usize {
+    fn set_size(&mut self, size: usize) {
+        self.size = size;
+    }
+
+    fn fill_size(&self) -> usize {
         self.fill_size
     }
 
-    pub fn set_fill_size(&mut self, fill_size: usize) {
+    fn set_fill_size(&mut self, fill_size: usize) {
         self.fill_size = fill_size;
     }
 
-    pub fn side(&self) -> MarketSide {
+    fn side(&self) -> MarketSide {
         self.side
     }
 
-    pub fn client(&self) -> &Client {
+    fn client(&self) -> &Client {
         &self.client
     }
 
-    pub fn order_id(&self) -> &String {
-        &self.order_id
+    fn order_id(&self) -> u64 {
+        self.order_id
+    }
+
+    fn set_order_id(&mut self, order_id: u64) {
+        self.order_id = order_id;
+    }
+
+    fn client_order_id(&self) -> &String {
+        &self.client_order_id
+    }
+
+    fn time_in_force(&self) -> TimeInForce {
+        self.time_in_force
+    }
+
+    fn account(&self) -> Option<&String> {
+        self.account.as_ref()
+    }
+
+    fn self_trade_prevention(&self) -> SelfTradePrevention {
+        self.self_trade_prevention
     }
 }
 
 #[derive(Debug)]
 pub struct LimitOrder {
     timestamp: DateTime<Utc>,
+    symbol: String,
     size: usize,
     fill_size: usize,
     side: MarketSide,
-    price: usize,
+    price: Price,
     client: Client,
-    order_id: String,
+    order_id: u64,
+    client_order_id: String,
+    time_in_force: TimeInForce,
+    display_size: Option<usize>,
+    post_only: Option<PostOnly>,
+    hidden: bool,
+    account: Option<String>,
+    self_trade_prevention: SelfTradePrevention,
+    /// Fill size at which the displayed slice of an iceberg order is used up.
+    slice_end: usize,
 }
 
 impl LimitOrder {
-    pub fn new(timestamp: DateTime<Utc>, size: usize, fill_size: usize, side: MarketSide, price: usize, client: Client, order_id: String) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(timestamp: DateTime<Utc>, symbol: String, size: usize, fill_size: usize, side: MarketSide, price: Price, client: Client, client_order_id: String) -> Self {
         LimitOrder {
             timestamp,
+            symbol,
             size,
             fill_size,
             side,
             price,
             client,
-            order_id,
+            order_id: 0,
+            client_order_id,
+            time_in_force: TimeInForce::Gtc,
+            display_size: None,
+            post_only: None,
+            hidden: false,
+            account: None,
+            self_trade_prevention: SelfTradePrevention::default(),
+            slice_end: 0,
         }
     }
 
-    pub fn timestamp(&self) -> &DateTime<Utc> {
+    pub fn with_time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.time_in_force = time_in_force;
+        self
+    }
+
+    /// Turns the order into an iceberg: while resting, only `display_size` of it is shown
+    /// and matched at a time, and the rest is kept in reserve.
+    pub fn with_display_size(mut self, display_size: usize) -> Self {
+        self.display_size = Some(display_size);
+        self
+    }
+
+    pub fn display_size(&self) -> Option<usize> {
+        self.display_size
+    }
+
+    /// Makes the order post-only: it may only add liquidity, never take it.
+    pub fn with_post_only(mut self, post_only: PostOnly) -> Self {
+        self.post_only = Some(post_only);
+        self
+    }
+
+    pub fn post_only(&self) -> Option<PostOnly> {
+        self.post_only
+    }
+
+    /// Hides the order: it rests and matches as usual but is left out of market data.
+    pub fn with_hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn with_account(mut self, account: String) -> Self {
+        self.account = Some(account);
+        self
+    }
+
+    pub fn with_self_trade_prevention(mut self, self_trade_prevention: SelfTradePrevention) -> Self {
+        self.self_trade_prevention = self_trade_prevention;
+        self
+    }
+
+    /// Quantity shown in the book: the unfilled part of the current slice for an iceberg,
+    /// the whole remaining size otherwise.
+    pub fn visible_size(&self) -> usize {
+        match self.display_size {
+            Some(_) => self.slice_end.min(self.size).saturating_sub(self.fill_size),
+            None => self.remaining_size(),
+        }
+    }
+
+    /// Shows the next slice of an iceberg order, up to its display size.
+    pub fn replenish(&mut self) {
+        if let Some(display_size) = self.display_size {
+            self.slice_end = self.fill_size + display_size.min(self.remaining_size());
+        }
+    }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
+
+    pub fn set_price(&mut self, price: Price) {
+        self.price = price;
+    }
+}
+
+impl Order for LimitOrder {
+    fn timestamp(&self) -> &DateTime<Utc> {
         &self.timestamp
     }
 
-    pub fn size(&self) -> usize {
+    fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    fn size(&self) -> usize {
         self.size
     }
 
-    pub fn fill_size(&self) -> usize {
+    fn set_size(&mut self, size: usize) {
+        self.size = size;
+    }
+
+    fn fill_size(&self) -> usize {
         self.fill_size
     }
 
-    pub fn set_fill_size(&mut self, fill_size: usize) {
+    fn set_fill_size(&mut self, fill_size: usize) {
         self.fill_size = fill_size;
     }
 
-    pub fn side(&self) -> MarketSide {
+    fn side(&self) -> MarketSide {
         self.side
     }
 
-    pub fn price(&self) -> usize {
-        self.price
+    fn client(&self) -> &Client {
+        &self.client
     }
 
-    pub fn client(&self) -> &Client {
-        &self.client
+    fn order_id(&self) -> u64 {
+        self.order_id
+    }
+
+    fn set_order_id(&mut self, order_id: u64) {
+        self.order_id = order_id;
+    }
+
+    fn client_order_id(&self) -> &String {
+        &self.client_order_id
+    }
+
+    fn time_in_force(&self) -> TimeInForce {
+        self.time_in_force
     }
 
-    pub fn order_id(&self) -> &String {
-        &self.order_id
+    fn account(&self) -> Option<&String> {
+        self.account.as_ref()
+    }
+
+    fn self_trade_prevention(&self) -> SelfTradePrevention {
+        self.self_trade_prevention
     }
 }
 
 impl fmt::Display for LimitOrder {
+    /// Icebergs only show their current slice, never the reserve.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.display_size.is_some() {
+            return write!(f, "LimitOrder: size: {}, fill_size: 0", self.visible_size());
+        }
         write!(f, "LimitOrder: size: {}, fill_size: {}", self.size, self.fill_size)
     }
 }
 
+/// Market order held back until the last traded price reaches `trigger_price`: at or
+/// above it for a buy stop, at or below it for a sell stop.
+#[derive(Debug)]
+pub struct StopOrder {
+    trigger_price: Price,
+    order: MarketOrder,
+}
+
+impl StopOrder {
+    pub fn new(trigger_price: Price, order: MarketOrder) -> Self {
+        StopOrder {
+            trigger_price,
+            order,
+        }
+    }
+
+    pub fn trigger_price(&self) -> Price {
+        self.trigger_price
+    }
+
+    pub fn set_trigger_price(&mut self, trigger_price: Price) {
+        self.trigger_price = trigger_price;
+    }
+
+    pub fn order(&self) -> &MarketOrder {
+        &self.order
+    }
+
+    pub fn order_mut(&mut self) -> &mut MarketOrder {
+        &mut self.order
+    }
+
+    /// The market order to enter once the stop is triggered.
+    pub fn into_order(self) -> MarketOrder {
+        self.order
+    }
+}
+
+/// Limit order held back until the last traded price reaches `trigger_price`, with the
+/// same trigger rule as `StopOrder`.
+#[derive(Debug)]
+pub struct StopLimitOrder {
+    trigger_price: Price,
+    order: LimitOrder,
+}
+
+impl StopLimitOrder {
+    pub fn new(trigger_price: Price, order: LimitOrder) -> Self {
+        StopLimitOrder {
+            trigger_price,
+            order,
+        }
+    }
+
+    pub fn trigger_price(&self) -> Price {
+        self.trigger_price
+    }
+
+    pub fn set_trigger_price(&mut self, trigger_price: Price) {
+        self.trigger_price = trigger_price;
+    }
+
+    pub fn order(&self) -> &LimitOrder {
+        &self.order
+    }
+
+    pub fn order_mut(&mut self) -> &mut LimitOrder {
+        &mut self.order
+    }
+
+    /// The limit order to enter once the stop is triggered.
+    pub fn into_order(self) -> LimitOrder {
+        self.order
+    }
+}
+
+#[derive(Debug)]
+pub struct CancelOrder {
+    timestamp: DateTime<Utc>,
+    symbol: String,
+    client: Client,
+    order_id: u64,
+}
+
+impl CancelOrder {
+    pub fn new(timestamp: DateTime<Utc>, symbol: String, client: Client, order_id: u64) -> Self {
+        CancelOrder {
+            timestamp,
+            symbol,
+            client,
+            order_id,
+        }
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn order_id(&self) -> u64 {
+        self.order_id
+    }
+}
+
+#[derive(Debug)]
+pub struct AmendOrder {
+    timestamp: DateTime<Utc>,
+    symbol: String,
+    client: Client,
+    order_id: u64,
+    price: Price,
+    size: usize,
+}
+
+impl AmendOrder {
+    pub fn new(timestamp: DateTime<Utc>, symbol: String, client: Client, order_id: u64, price: Price, size: usize) -> Self {
+        AmendOrder {
+            timestamp,
+            symbol,
+            client,
+            order_id,
+            price,
+            size,
+        }
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn order_id(&self) -> u64 {
+        self.order_id
+    }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
+
+    pub fn set_price(&mut self, price: Price) {
+        self.price = price;
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+/// Request for a level-2 snapshot of the top `levels` prices on each side of a book.
+#[derive(Debug)]
+pub struct DepthRequest {
+    timestamp: DateTime<Utc>,
+    symbol: String,
+    client: Client,
+    levels: usize,
+}
+
+impl DepthRequest {
+    pub fn new(timestamp: DateTime<Utc>, symbol: String, client: Client, levels: usize) -> Self {
+        DepthRequest {
+            timestamp,
+            symbol,
+            client,
+            levels,
+        }
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels
+    }
+}
+
+/// Operator action on the trading of a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlAction {
+    Halt,
+    Resume,
+}
+
+/// Operator request to halt or resume trading in a book.
+#[derive(Debug)]
+pub struct ControlRequest {
+    timestamp: DateTime<Utc>,
+    symbol: String,
+    client: Client,
+    action: ControlAction,
+}
+
+impl ControlRequest {
+    pub fn new(timestamp: DateTime<Utc>, symbol: String, client: Client, action: ControlAction) -> Self {
+        ControlRequest {
+            timestamp,
+            symbol,
+            client,
+            action,
+        }
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn action(&self) -> ControlAction {
+        self.action
+    }
+}
+
 #[derive(Debug)]
 pub enum Orders {
     Market(MarketOrder),
     Limit(LimitOrder),
+    Cancel(CancelOrder),
+    Amend(AmendOrder),
+    Stop(StopOrder),
+    StopLimit(StopLimitOrder),
+    Depth(DepthRequest),
+    Control(ControlRequest),
+}
+
+impl Orders {
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        match self {
+            Orders::Market(order) => order.timestamp(),
+            Orders::Limit(order) => order.timestamp(),
+            Orders::Cancel(order) => order.timestamp(),
+            Orders::Amend(order) => order.timestamp(),
+            Orders::Stop(order) => order.order().timestamp(),
+            Orders::StopLimit(order) => order.order().timestamp(),
+            Orders::Depth(request) => request.timestamp(),
+            Orders::Control(request) => request.timestamp(),
+        }
+    }
+
+    pub fn symbol(&self) -> &String {
+        match self {
+            Orders::Market(order) => order.symbol(),
+            Orders::Limit(order) => order.symbol(),
+            Orders::Cancel(order) => order.symbol(),
+            Orders::Amend(order) => order.symbol(),
+            Orders::Stop(order) => order.order().symbol(),
+            Orders::StopLimit(order) => order.order().symbol(),
+            Orders::Depth(request) => request.symbol(),
+            Orders::Control(request) => request.symbol(),
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        match self {
+            Orders::Market(order) => order.client(),
+            Orders::Limit(order) => order.client(),
+            Orders::Cancel(order) => order.client(),
+            Orders::Amend(order) => order.client(),
+            Orders::Stop(order) => order.order().client(),
+            Orders::StopLimit(order) => order.order().client(),
+            Orders::Depth(request) => request.client(),
+            Orders::Control(request) => request.client(),
+        }
+    }
+
+    /// The order being entered, for every request that creates one (not cancels, amends,
+    /// depth or operator requests).
+    pub fn new_order(&self) -> Option<&dyn Order> {
+        match self {
+            Orders::Market(order) => Some(order),
+            Orders::Limit(order) => Some(order),
+            Orders::Cancel(_) | Orders::Amend(_) | Orders::Depth(_) | Orders::Control(_) => None,
+            Orders::Stop(order) => Some(order.order()),
+            Orders::StopLimit(order) => Some(order.order()),
+        }
+    }
+
+    pub fn new_order_mut(&mut self) -> Option<&mut dyn Order> {
+        match self {
+            Orders::Market(order) => Some(order),
+            Orders::Limit(order) => Some(order),
+            Orders::Cancel(_) | Orders::Amend(_) | Orders::Depth(_) | Orders::Control(_) => None,
+            Orders::Stop(order) => Some(order.order_mut()),
+            Orders::StopLimit(order) => Some(order.order_mut()),
+        }
+    }
 }
 
 impl From<MarketOrder> for Orders {
@@ -141,3 +723,39 @@ impl From<LimitOrder> for Orders {
         Orders::Limit(order)
     }
 }
+
+impl From<CancelOrder> for Orders {
+    fn from(order: CancelOrder) -> Self {
+        Orders::Cancel(order)
+    }
+}
+
+impl From<AmendOrder> for Orders {
+    fn from(order: AmendOrder) -> Self {
+        Orders::Amend(order)
+    }
+}
+
+impl From<StopOrder> for Orders {
+    fn from(order: StopOrder) -> Self {
+        Orders::Stop(order)
+    }
+}
+
+impl From<StopLimitOrder> for Orders {
+    fn from(order: StopLimitOrder) -> Self {
+        Orders::StopLimit(order)
+    }
+}
+
+impl From<DepthRequest> for Orders {
+    fn from(request: DepthRequest) -> Self {
+        Orders::Depth(request)
+    }
+}
+
+impl From<ControlRequest> for Orders {
+    fn from(request: ControlRequest) -> Self {
+        Orders::Control(request)
+    }
+}
@@ -0,0 +1,214 @@
+use core::fmt;
+use std::{cmp::Ordering, str::FromStr};
+
+use crate::protocol::ParseError;
+
+/// Exact fixed-point decimal price: `units` counted in steps of 10^-`scale`, so 101.25
+/// at scale 2 is stored as 10125. No floats are involved anywhere.
+///
+/// Prices compare by value, so 101.5 and 101.50 are equal even though their scales
+/// differ. Books keep every price at the scale of their instrument, which also fixes how
+/// prices are printed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Price {
+    units: u64,
+    scale: u32,
+}
+
+impl Price {
+    /// Most decimals a price may carry.
+    pub const MAX_SCALE: u32 = 9;
+
+    pub fn new(units: u64, scale: u32) -> Self {
+        assert!(scale <= Self::MAX_SCALE, "price scale {scale} above {}", Self::MAX_SCALE);
+        Price {
+            units,
+            scale,
+        }
+    }
+
+    pub fn units(&self) -> u64 {
+        self.units
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.units == 0
+    }
+
+    /// The same value written with `scale` decimals, or `None` if that would drop
+    /// non-zero digits or overflow.
+    pub fn rescale(&self, scale: u32) -> Option<Price> {
+        if scale > Self::MAX_SCALE {
+            return None;
+        }
+        let units = if scale >= self.scale {
+            self.units.checked_mul(10u64.pow(scale - self.scale))?
+        } else {
+            let divisor = 10u64.pow(self.scale - scale);
+            if !self.units.is_multiple_of(divisor) {
+                return None;
+            }
+            self.units / divisor
+        };
+        Some(Price::new(units, scale))
+    }
+
+    pub fn checked_add(&self, other: Price) -> Option<Price> {
+        let (a, b) = Self::align(*self, other)?;
+        Some(Price::new(a.units.checked_add(b.units)?, a.scale))
+    }
+
+    pub fn checked_sub(&self, other: Price) -> Option<Price> {
+        let (a, b) = Self::align(*self, other)?;
+        Some(Price::new(a.units.checked_sub(b.units)?, a.scale))
+    }
+
+    /// `bps` basis points of this price at the same scale, rounded down.
+    pub fn basis_points(&self, bps: usize) -> Price {
+        let units = u128::from(self.units) * bps as u128 / 10_000;
+        Price::new(u64::try_from(units).unwrap_or(u64::MAX), self.scale)
+    }
+
+    /// Returns true if the price is a whole number of `tick`s.
+    pub fn is_multiple_of(&self, tick: Price) -> bool {
+        match Self::align(*self, tick) {
+            Some((price, tick)) if !tick.is_zero() => price.units.is_multiple_of(tick.units),
+            _ => false,
+        }
+    }
+
+    /// Both prices at the larger of their two scales.
+    fn align(a: Price, b: Price) -> Option<(Price, Price)> {
+        let scale = a.scale.max(b.scale);
+        Some((a.rescale(scale)?, b.rescale(scale)?))
+    }
+
+    /// Value in units of 10^-`MAX_SCALE`, which every price fits in exactly.
+    fn normalized(&self) -> u128 {
+        u128::from(self.units) * 10u128.pow(Self::MAX_SCALE - self.scale)
+    }
+}
+
+impl PartialEq for Price {
+    fn eq(&self, other: &Self) -> bool {
+        self.normalized() == other.normalized()
+    }
+}
+
+impl Eq for Price {}
+
+impl PartialOrd for Price {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.normalized().cmp(&other.normalized())
+    }
+}
+
+/// Parses a plain decimal such as `101`, `101.25` or `0.5`, keeping as many decimals as
+/// were written.
+impl FromStr for Price {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (whole, frac) = s.split_once('.').unwrap_or((s, ""));
+        let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if whole.is_empty() || !digits(whole) || !digits(frac) || (s.contains('.') && frac.is_empty()) {
+            return Err(ParseError::InvalidPrice);
+        }
+
+        let scale = u32::try_from(frac.len()).map_err(|_| ParseError::InvalidPrice)?;
+        if scale > Self::MAX_SCALE {
+            return Err(ParseError::InvalidPrice);
+        }
+        let units = format!("{whole}{frac}").parse().map_err(|_| ParseError::InvalidPrice)?;
+        Ok(Price::new(units, scale))
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.scale == 0 {
+            return write!(f, "{}", self.units);
+        }
+        let divisor = 10u64.pow(self.scale);
+        write!(f, "{}.{:0width$}", self.units / divisor, self.units % divisor, width = self.scale as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(s: &str) -> Price {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_decimals() {
+        assert_eq!((price("101").units(), price("101").scale()), (101, 0));
+        assert_eq!((price("101.25").units(), price("101.25").scale()), (10125, 2));
+        assert_eq!((price("0.5").units(), price("0.5").scale()), (5, 1));
+        assert_eq!((price("007.000000001").units(), price("007.000000001").scale()), (7_000_000_001, 9));
+        assert_eq!(price("18446744073709551615").units(), u64::MAX);
+    }
+
+    #[test]
+    fn rejects_malformed_prices() {
+        let malformed = ["", ".", "1.", ".5", "-1", "+1", "1.2.3", "1,5", "1e3", " 1", "abc", "1.0000000001", "18446744073709551616", "1844674407370955161.6"];
+        for s in malformed {
+            assert_eq!(s.parse::<Price>(), Err(ParseError::InvalidPrice), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn displays_at_its_scale() {
+        for s in ["0", "101", "101.25", "0.05", "100.00", "5000.000000001"] {
+            assert_eq!(price(s).to_string(), s);
+        }
+        assert_eq!(Price::new(5, 3).to_string(), "0.005");
+    }
+
+    #[test]
+    fn rescales_without_losing_digits() {
+        assert_eq!(price("101.5").rescale(3).map(|p| p.to_string()), Some("101.500".to_string()));
+        assert_eq!(price("101.500").rescale(1).map(|p| p.to_string()), Some("101.5".to_string()));
+        assert_eq!(price("101.25").rescale(1), None);
+        assert_eq!(price("1").rescale(Price::MAX_SCALE + 1), None);
+        assert_eq!(Price::new(u64::MAX, 0).rescale(1), None);
+    }
+
+    #[test]
+    fn compares_by_value_across_scales() {
+        assert_eq!(price("101.5"), price("101.50"));
+        assert_eq!(price("100"), price("100.000000000"));
+        assert!(price("101.49") < price("101.5"));
+        assert!(price("99.999") < price("100"));
+        assert!(price("0.000000001") > price("0"));
+        assert_eq!(price("1.10").cmp(&price("1.1")), Ordering::Equal);
+
+        let mut prices = [price("2"), price("1.99"), price("2.001"), price("0.5")];
+        prices.sort();
+        assert_eq!(prices.iter().map(Price::to_string).collect::<Vec<_>>(), ["0.5", "1.99", "2", "2.001"]);
+    }
+
+    #[test]
+    fn arithmetic_aligns_scales() {
+        assert_eq!(price("100").checked_add(price("0.25")).map(|p| p.to_string()), Some("100.25".to_string()));
+        assert_eq!(price("100.00").checked_sub(price("0.5")).map(|p| p.to_string()), Some("99.50".to_string()));
+        assert_eq!(price("1").checked_sub(price("1.01")), None);
+        assert_eq!(Price::new(u64::MAX, 0).checked_add(price("1")), None);
+        assert_eq!(price("100.00").basis_points(250).to_string(), "2.50");
+        assert!(price("101.25").is_multiple_of(price("0.05")));
+        assert!(!price("101.26").is_multiple_of(price("0.05")));
+        assert!(!price("101").is_multiple_of(price("0")));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::orders::LimitOrder;
+
+/// Stable reference to a resting order inside an `OrderArena`. A handle stays valid until
+/// the order is removed, no matter how many orders are added or removed around it.
+pub type OrderHandle = usize;
+
+#[derive(Debug)]
+struct Node {
+    order: LimitOrder,
+    prev: Option<OrderHandle>,
+    next: Option<OrderHandle>,
+}
+
+/// Slab holding every resting order of a book. Freed slots are reused, so handles are
+/// plain indices and lookups are O(1).
+#[derive(Debug, Default)]
+pub struct OrderArena {
+    slots: Vec<Option<Node>>,
+    free: Vec<OrderHandle>,
+}
+
+impl OrderArena {
+    pub fn new() -> Self {
+        OrderArena {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, handle: OrderHandle) -> Option<&LimitOrder> {
+        self.slots.get(handle)?.as_ref().map(|node| &node.order)
+    }
+
+    pub fn get_mut(&mut self, handle: OrderHandle) -> Option<&mut LimitOrder> {
+        self.slots.get_mut(handle)?.as_mut().map(|node| &mut node.order)
+    }
+
+    fn node(&self, handle: OrderHandle) -> &Node {
+        self.slots[handle].as_ref().expect("dangling order handle")
+    }
+
+    fn node_mut(&mut self, handle: OrderHandle) -> &mut Node {
+        self.slots[handle].as_mut().expect("dangling order handle")
+    }
+
+    fn insert(&mut self, node: Node) -> OrderHandle {
+        if let Some(handle) = self.free.pop() {
+            self.slots[handle] = Some(node);
+            handle
+        } else {
+            self.slots.push(Some(node));
+            self.slots.len() - 1
+        }
+    }
+
+    fn take(&mut self, handle: OrderHandle) -> Node {
+        let node = self.slots[handle].take().expect("dangling order handle");
+        self.free.push(handle);
+        node
+    }
+}
+
+/// FIFO queue of the orders resting at one price, stored as a doubly linked list threaded
+/// through an `OrderArena` so any order can be unlinked in O(1).
+#[derive(Debug, Default)]
+pub struct PriceLevel {
+    head: Option<OrderHandle>,
+    tail: Option<OrderHandle>,
+    len: usize,
+}
+
+impl PriceLevel {
+    pub fn new() -> Self {
+        PriceLevel {
+            head: None,
+            tail: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn front(&self) -> Option<OrderHandle> {
+        self.head
+    }
+
+    pub fn push_back(&mut self, arena: &mut OrderArena, order: LimitOrder) -> OrderHandle {
+        let handle = arena.insert(Node {
+            order,
+            prev: self.tail,
+            next: None,
+        });
+
+        match self.tail {
+            Some(tail) => arena.node_mut(tail).next = Some(handle),
+            None => self.head = Some(handle),
+        }
+        self.tail = Some(handle);
+        self.len += 1;
+
+        handle
+    }
+
+    /// Unlinks `handle` from this level and frees its slot. The handle must belong to this level.
+    pub fn remove(&mut self, arena: &mut OrderArena, handle: OrderHandle) -> LimitOrder {
+        let node = arena.take(handle);
+
+        match node.prev {
+            Some(prev) => arena.node_mut(prev).next = node.next,
+            None => self.head = node.next,
+        }
+        match node.next {
+            Some(next) => arena.node_mut(next).prev = node.prev,
+            None => self.tail = node.prev,
+        }
+        self.len -= 1;
+
+        node.order
+    }
+
+    pub fn iter<'a>(&self, arena: &'a OrderArena) -> impl Iterator<Item = (OrderHandle, &'a LimitOrder)> {
+        let mut cursor = self.head;
+        std::iter::from_fn(move || {
+            let handle = cursor?;
+            let node = arena.node(handle);
+            cursor = node.next;
+            Some((handle, &node.order))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{orders::{MarketSide, Order}, testing::client};
+    use chrono::DateTime;
+
+    fn order(order_id: u64) -> LimitOrder {
+        let (client, _rx) = client(1);
+        let mut order = LimitOrder::new(DateTime::UNIX_EPOCH, "T".to_string(), 1, 0, MarketSide::Bid, "100".parse().unwrap(), client, format!("c{order_id}"));
+        order.set_order_id(order_id);
+        order
+    }
+
+    fn order_ids(level: &PriceLevel, arena: &OrderArena) -> Vec<u64> {
+        level.iter(arena).map(|(_, order)| order.order_id()).collect()
+    }
+
+    #[test]
+    fn remove_unlinks_anywhere_in_the_queue() {
+        let mut arena = OrderArena::new();
+        let mut level = PriceLevel::new();
+        let handles: Vec<OrderHandle> = (1..=5).map(|order_id| level.push_back(&mut arena, order(order_id))).collect();
+
+        assert_eq!(level.remove(&mut arena, handles[2]).order_id(), 3);
+        assert_eq!(order_ids(&level, &arena), [1, 2, 4, 5]);
+        assert_eq!(level.remove(&mut arena, handles[0]).order_id(), 1);
+        assert_eq!(level.front(), Some(handles[1]));
+        assert_eq!(level.remove(&mut arena, handles[4]).order_id(), 5);
+        assert_eq!(order_ids(&level, &arena), [2, 4]);
+        assert_eq!(level.len(), 2);
+        assert!(arena.get(handles[0]).is_none());
+
+        // A new order goes to the back, whichever slot it reuses.
+        level.push_back(&mut arena, order(6));
+        assert_eq!(order_ids(&level, &arena), [2, 4, 6]);
+        level.remove(&mut arena, handles[1]);
+        level.remove(&mut arena, handles[3]);
+        assert_eq!(order_ids(&level, &arena), [6]);
+    }
+
+    #[test]
+    fn freed_slots_are_reused() {
+        let mut arena = OrderArena::new();
+        let mut level = PriceLevel::new();
+        let first = level.push_back(&mut arena, order(1));
+        let second = level.push_back(&mut arena, order(2));
+        level.remove(&mut arena, first);
+
+        let third = level.push_back(&mut arena, order(3));
+        assert_eq!(third, first);
+        assert_eq!(arena.slots.len(), 2);
+        assert_eq!(arena.get(third).map(LimitOrder::order_id), Some(3));
+        assert_eq!(arena.get(second).map(LimitOrder::order_id), Some(2));
+
+        level.remove(&mut arena, second);
+        level.remove(&mut arena, third);
+        assert!(level.is_empty());
+        assert_eq!(level.front(), None);
+        assert_eq!(arena.free.len(), 2);
+    }
+}
@@ -0,0 +1,714 @@
+use core::fmt;
+
+use std::ops::RangeBounds;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    client_handler::{Client, Owner},
+    execution_report::ExecutionReport,
+    market_data::{Depth, DepthLevel, LevelAction, MarketData, MarketDataEvent, OrderAction, OrderSnapshot, QueuedOrder},
+    orderbook::WorkingOrder,
+    orders::*,
+    price::Price,
+    stop_book::PendingStop,
+    trade::Trade,
+};
+
+/// Reasons a line received from a client cannot be turned into an order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownCommand,
+    InvalidOrderType,
+    WrongFieldCount,
+    InvalidSymbol,
+    InvalidPrice,
+    InvalidQuantity,
+    InvalidOrderId,
+    InvalidTimeInForce,
+    InvalidProtection,
+    InvalidDisplaySize,
+    InvalidOption,
+    InvalidDepth,
+    Unauthorized,
+    InvalidLogin,
+    LoginInUse,
+}
+
+impl ParseError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::UnknownCommand => "UNKNOWN_COMMAND",
+            ParseError::InvalidOrderType => "INVALID_ORDER_TYPE",
+            ParseError::WrongFieldCount => "WRONG_FIELD_COUNT",
+            ParseError::InvalidSymbol => "INVALID_SYMBOL",
+            ParseError::InvalidPrice => "INVALID_PRICE",
+            ParseError::InvalidQuantity => "INVALID_QUANTITY",
+            ParseError::InvalidOrderId => "INVALID_ORDER_ID",
+            ParseError::InvalidTimeInForce => "INVALID_TIME_IN_FORCE",
+            ParseError::InvalidProtection => "INVALID_PROTECTION",
+            ParseError::InvalidDisplaySize => "INVALID_DISPLAY_SIZE",
+            ParseError::InvalidOption => "INVALID_OPTION",
+            ParseError::InvalidDepth => "INVALID_DEPTH",
+            ParseError::Unauthorized => "UNAUTHORIZED",
+            ParseError::InvalidLogin => "INVALID_LOGIN",
+            ParseError::LoginInUse => "LOGIN_IN_USE",
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ParseError::UnknownCommand => "unknown command: use buy, sell, cancel, amend or book",
+            ParseError::InvalidOrderType => "invalid order type: choose market, limit, stop or stoplimit",
+            ParseError::WrongFieldCount => "wrong number of fields for this command",
+            ParseError::InvalidSymbol => "symbol must be letters, digits or dots",
+            ParseError::InvalidPrice => "price must be a positive decimal",
+            ParseError::InvalidQuantity => "quantity must be a positive integer",
+            ParseError::InvalidOrderId => "order id must be a non-negative integer",
+            ParseError::InvalidTimeInForce => "invalid time in force: limit orders take gtc, day, ioc or fok, market orders ioc or fok",
+            ParseError::InvalidProtection => "protection must be a price or a percentage up to 100% such as 2.5%",
+            ParseError::InvalidDisplaySize => "display size must be a positive integer no larger than the quantity",
+            ParseError::InvalidOption => "unknown or unsupported option for this order type",
+            ParseError::InvalidDepth => "depth must be a positive number of price levels",
+            ParseError::Unauthorized => "operator connections must start with auth <secret>",
+            ParseError::InvalidLogin => "login must come first, once, with a name of up to 32 letters, digits, _, - or .",
+            ParseError::LoginInUse => "another connection is logged in with this name",
+        };
+        write!(f, "{msg}")
+    }
+}
+
+pub fn expect_fields(parts: &[&str], count: impl RangeBounds<usize>) -> Result<(), ParseError> {
+    if count.contains(&parts.len()) {
+        Ok(())
+    } else {
+        Err(ParseError::WrongFieldCount)
+    }
+}
+
+pub fn parse_price(field: &str) -> Result<Price, ParseError> {
+    match field.parse::<Price>() {
+        Ok(price) if !price.is_zero() => Ok(price),
+        _ => Err(ParseError::InvalidPrice),
+    }
+}
+
+fn parse_qty(field: &str) -> Result<usize, ParseError> {
+    match field.parse() {
+        Ok(qty) if qty > 0 => Ok(qty),
+        _ => Err(ParseError::InvalidQuantity),
+    }
+}
+
+/// Symbols are case insensitive on the wire and kept upper case.
+pub fn parse_symbol(field: &str) -> Result<String, ParseError> {
+    if field.is_empty() || !field.chars().all(|c| c.is_ascii_alphanumeric() || c == '.') {
+        return Err(ParseError::InvalidSymbol);
+    }
+    Ok(field.to_ascii_uppercase())
+}
+
+fn parse_order_id(field: &str) -> Result<u64, ParseError> {
+    field.parse().map_err(|_| ParseError::InvalidOrderId)
+}
+
+fn parse_depth(field: &str) -> Result<usize, ParseError> {
+    match field.parse() {
+        Ok(levels) if levels > 0 => Ok(levels),
+        _ => Err(ParseError::InvalidDepth),
+    }
+}
+
+fn parse_time_in_force(field: &str) -> Result<TimeInForce, ParseError> {
+    match field {
+        "gtc" => Ok(TimeInForce::Gtc),
+        "day" => Ok(TimeInForce::Day),
+        "ioc" => Ok(TimeInForce::Ioc),
+        "fok" => Ok(TimeInForce::Fok),
+        _ => Err(ParseError::InvalidTimeInForce),
+    }
+}
+
+/// Widest protection band, 100%.
+const MAX_PROTECTION_BPS: usize = 10_000;
+
+/// Parses `<price>` as an absolute protection price or `<pct>%` (up to two decimals, at
+/// most 100%) as a band around the best opposite price.
+fn parse_protection(value: &str) -> Result<PriceProtection, ParseError> {
+    let Some(pct) = value.strip_suffix('%') else {
+        return parse_price(value)
+            .map(PriceProtection::Limit)
+            .map_err(|_| ParseError::InvalidProtection);
+    };
+
+    let (whole, frac) = pct.split_once('.').unwrap_or((pct, ""));
+    let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if whole.is_empty() || !digits(whole) || frac.len() > 2 || !digits(frac) {
+        return Err(ParseError::InvalidProtection);
+    }
+    let whole: usize = whole.parse().map_err(|_| ParseError::InvalidProtection)?;
+    let frac: usize = format!("{frac:0<2}").parse().map_err(|_| ParseError::InvalidProtection)?;
+
+    whole
+        .checked_mul(100)
+        .and_then(|bps| bps.checked_add(frac))
+        .filter(|&bps| bps <= MAX_PROTECTION_BPS)
+        .map(PriceProtection::BasisPoints)
+        .ok_or(ParseError::InvalidProtection)
+}
+
+/// Optional trailing fields of a new order line: a time in force keyword, flags and
+/// `key=value` settings.
+#[derive(Default)]
+struct OrderOptions {
+    time_in_force: Option<TimeInForce>,
+    protection: Option<PriceProtection>,
+    display_size: Option<usize>,
+    post_only: Option<PostOnly>,
+    hidden: bool,
+    account: Option<String>,
+    self_trade_prevention: Option<SelfTradePrevention>,
+}
+
+impl OrderOptions {
+    /// Returns true if any option that only applies to orders resting at a price is set.
+    fn has_limit_only(&self) -> bool {
+        self.display_size.is_some() || self.post_only.is_some() || self.hidden
+    }
+}
+
+fn parse_options(fields: &[&str]) -> Result<OrderOptions, ParseError> {
+    let mut options = OrderOptions::default();
+    for field in fields {
+        if let Some(account) = field.strip_prefix("account=").filter(|account| !account.is_empty()) {
+            options.account = Some(account.to_string());
+            continue;
+        }
+
+        let field = field.to_lowercase();
+        match field.split_once('=') {
+            Some(("protect", value)) => options.protection = Some(parse_protection(value)?),
+            Some(("display", value)) => {
+                let display_size = parse_qty(value).map_err(|_| ParseError::InvalidDisplaySize)?;
+                options.display_size = Some(display_size);
+            },
+            Some(("postonly", "reject")) => options.post_only = Some(PostOnly::Reject),
+            Some(("postonly", "reprice")) => options.post_only = Some(PostOnly::Reprice),
+            Some(("stp", value)) => options.self_trade_prevention = Some(parse_self_trade_prevention(value)?),
+            Some(_) => return Err(ParseError::InvalidOption),
+            None if field == "postonly" => options.post_only = Some(PostOnly::Reject),
+            None if field == "hidden" => options.hidden = true,
+            None => options.time_in_force = Some(parse_time_in_force(&field)?),
+        }
+    }
+    if options.self_trade_prevention.is_some() && options.account.is_none() {
+        return Err(ParseError::InvalidOption);
+    }
+    Ok(options)
+}
+
+fn parse_self_trade_prevention(value: &str) -> Result<SelfTradePrevention, ParseError> {
+    match value {
+        "cn" => Ok(SelfTradePrevention::CancelNewest),
+        "co" => Ok(SelfTradePrevention::CancelOldest),
+        "cb" => Ok(SelfTradePrevention::CancelBoth),
+        "dc" => Ok(SelfTradePrevention::DecrementAndCancel),
+        _ => Err(ParseError::InvalidOption),
+    }
+}
+
+/// Applies the options of a market or stop line. Market orders never rest, so GTC, DAY
+/// and the resting-only options are refused.
+fn with_market_options(order: MarketOrder, options: OrderOptions) -> Result<MarketOrder, ParseError> {
+    if options.has_limit_only() {
+        return Err(ParseError::InvalidOption);
+    }
+    let time_in_force = match options.time_in_force.unwrap_or(TimeInForce::Ioc) {
+        TimeInForce::Gtc | TimeInForce::Day => return Err(ParseError::InvalidTimeInForce),
+        time_in_force => time_in_force,
+    };
+
+    let mut order = order.with_time_in_force(time_in_force);
+    if let Some(protection) = options.protection {
+        order = order.with_protection(protection);
+    }
+    if let Some(account) = options.account {
+        order = order
+            .with_account(account)
+            .with_self_trade_prevention(options.self_trade_prevention.unwrap_or_default());
+    }
+    Ok(order)
+}
+
+/// Applies the options of a limit or stop-limit line. Price protection only makes sense
+/// for market orders, post-only orders must be able to rest, a hidden order shows
+/// nothing so it cannot be an iceberg, and an iceberg cannot show more than its own
+/// quantity.
+fn with_limit_options(order: LimitOrder, options: OrderOptions) -> Result<LimitOrder, ParseError> {
+    let time_in_force = options.time_in_force.unwrap_or(TimeInForce::Gtc);
+    let rests = matches!(time_in_force, TimeInForce::Gtc | TimeInForce::Day);
+    if options.protection.is_some()
+        || (options.post_only.is_some() && !rests)
+        || (options.hidden && options.display_size.is_some()) {
+        return Err(ParseError::InvalidOption);
+    }
+
+    let mut order = order.with_time_in_force(time_in_force);
+    if let Some(post_only) = options.post_only {
+        order = order.with_post_only(post_only);
+    }
+    if options.hidden {
+        order = order.with_hidden();
+    }
+    if let Some(account) = options.account {
+        order = order
+            .with_account(account)
+            .with_self_trade_prevention(options.self_trade_prevention.unwrap_or_default());
+    }
+    if let Some(display_size) = options.display_size {
+        if display_size > order.size() {
+            return Err(ParseError::InvalidDisplaySize);
+        }
+        order = order.with_display_size(display_size);
+    }
+    Ok(order)
+}
+
+/// Parses a line from a client into a request stamped with `timestamp`.
+pub fn parse_request(input: &str, client: Client, timestamp: DateTime<Utc>) -> Result<Orders, ParseError> {
+    let parts: Vec<&str> = input.split_whitespace().collect();
+    let command = parts.first().ok_or(ParseError::UnknownCommand)?.to_lowercase();
+
+    let side = match command.as_str() {
+        "cancel" => {
+            expect_fields(&parts, 3..=3)?;
+            let symbol = parse_symbol(parts[1])?;
+            let order_id = parse_order_id(parts[2])?;
+            return Ok(CancelOrder::new(timestamp, symbol, client, order_id).into());
+        },
+        "amend" => {
+            expect_fields(&parts, 5..=5)?;
+            let symbol = parse_symbol(parts[1])?;
+            let order_id = parse_order_id(parts[2])?;
+            let price = parse_price(parts[3])?;
+            let qty = parse_qty(parts[4])?;
+            return Ok(AmendOrder::new(timestamp, symbol, client, order_id, price, qty).into());
+        },
+        "book" => {
+            expect_fields(&parts, 3..=3)?;
+            let symbol = parse_symbol(parts[1])?;
+            let levels = parse_depth(parts[2])?;
+            return Ok(DepthRequest::new(timestamp, symbol, client, levels).into());
+        },
+        "buy" => MarketSide::Bid,
+        "sell" => MarketSide::Ask,
+        _ => return Err(ParseError::UnknownCommand),
+    };
+
+    let order_type = parts.get(1).ok_or(ParseError::WrongFieldCount)?.to_lowercase();
+    let symbol = parse_symbol(parts.get(2).ok_or(ParseError::WrongFieldCount)?)?;
+
+    match order_type.as_str() {
+        "market" => {
+            expect_fields(&parts, 5..)?;
+            let qty = parse_qty(parts[3])?;
+            let options = parse_options(&parts[5..])?;
+            let order = MarketOrder::new(timestamp, symbol, qty, 0, side, client, parts[4].to_string());
+            let order = with_market_options(order, options)?;
+            Ok(order.into())
+        }
+
+        "limit" => {
+            expect_fields(&parts, 6..)?;
+            let price = parse_price(parts[3])?;
+            let qty = parse_qty(parts[4])?;
+            let options = parse_options(&parts[6..])?;
+            let order = LimitOrder::new(timestamp, symbol, qty, 0, side, price, client, parts[5].to_string());
+            Ok(with_limit_options(order, options)?.into())
+        },
+
+        "stop" => {
+            expect_fields(&parts, 6..)?;
+            let trigger_price = parse_price(parts[3])?;
+            let qty = parse_qty(parts[4])?;
+            let options = parse_options(&parts[6..])?;
+            let order = MarketOrder::new(timestamp, symbol, qty, 0, side, client, parts[5].to_string());
+            let order = with_market_options(order, options)?;
+            Ok(StopOrder::new(trigger_price, order).into())
+        },
+
+        "stoplimit" => {
+            expect_fields(&parts, 7..)?;
+            let trigger_price = parse_price(parts[3])?;
+            let price = parse_price(parts[4])?;
+            let qty = parse_qty(parts[5])?;
+            let options = parse_options(&parts[7..])?;
+            let order = LimitOrder::new(timestamp, symbol, qty, 0, side, price, client, parts[6].to_string());
+            Ok(StopLimitOrder::new(trigger_price, with_limit_options(order, options)?).into())
+        },
+
+        _ => Err(ParseError::InvalidOrderType),
+    }
+}
+
+/// Name of a `login <name>` line, checked to be a valid login, or `None` for any other
+/// line.
+pub fn parse_login(input: &str) -> Option<Result<&str, ParseError>> {
+    let parts: Vec<&str> = input.split_whitespace().collect();
+    if !parts.first()?.eq_ignore_ascii_case("login") {
+        return None;
+    }
+    match parts[..] {
+        [_, name] if Owner::login(name).is_some() => Some(Ok(name)),
+        _ => Some(Err(ParseError::InvalidLogin)),
+    }
+}
+
+/// Parses a line from an operator connection: `halt <symbol>` or `resume <symbol>`.
+pub fn parse_operator_request(input: &str, client: Client, timestamp: DateTime<Utc>) -> Result<Orders, ParseError> {
+    let parts: Vec<&str> = input.split_whitespace().collect();
+    let action = match parts.first().map(|command| command.to_lowercase()).as_deref() {
+        Some("halt") => ControlAction::Halt,
+        Some("resume") => ControlAction::Resume,
+        _ => return Err(ParseError::UnknownCommand),
+    };
+    expect_fields(&parts, 2..=2)?;
+    let symbol = parse_symbol(parts[1])?;
+    Ok(ControlRequest::new(timestamp, symbol, client, action).into())
+}
+
+/// Parses any line a book may be sent, whether it came from a client or an operator, as
+/// when replaying a journal.
+pub fn parse_book_request(input: &str, client: Client, timestamp: DateTime<Utc>) -> Result<Orders, ParseError> {
+    match parse_operator_request(input, client.clone(), timestamp) {
+        Err(ParseError::UnknownCommand) => parse_request(input, client, timestamp),
+        result => result,
+    }
+}
+
+/// Writes a request back as the line a client would send for it, options included, so
+/// that parsing the line gives the same request again. Depth requests change nothing in
+/// a book and have no line.
+pub fn format_request(request: &Orders) -> Option<String> {
+    let line = match request {
+        Orders::Market(order) => format!("{} market {} {} {}{}", serialize_side(order.side()), order.symbol(), order.size(), order.client_order_id(), market_options(order)),
+        Orders::Limit(order) => format_limit(order),
+        Orders::Stop(stop) => format_stop(stop),
+        Orders::StopLimit(stop) => format_stop_limit(stop),
+        Orders::Cancel(cancel) => format!("cancel {} {}", cancel.symbol(), cancel.order_id()),
+        Orders::Amend(amend) => format!("amend {} {} {} {}", amend.symbol(), amend.order_id(), amend.price(), amend.size()),
+        Orders::Control(control) => match control.action() {
+            ControlAction::Halt => format!("halt {}", control.symbol()),
+            ControlAction::Resume => format!("resume {}", control.symbol()),
+        },
+        Orders::Depth(_) => return None,
+    };
+    Some(line)
+}
+
+fn format_limit(order: &LimitOrder) -> String {
+    format!("{} limit {} {} {} {}{}", serialize_side(order.side()), order.symbol(), order.price(), order.size(), order.client_order_id(), limit_options(order))
+}
+
+fn format_stop(stop: &StopOrder) -> String {
+    let order = stop.order();
+    format!("{} stop {} {} {} {}{}", serialize_side(order.side()), order.symbol(), stop.trigger_price(), order.size(), order.client_order_id(), market_options(order))
+}
+
+fn format_stop_limit(stop: &StopLimitOrder) -> String {
+    let order = stop.order();
+    format!("{} stoplimit {} {} {} {} {}{}", serialize_side(order.side()), order.symbol(), stop.trigger_price(), order.price(), order.size(), order.client_order_id(), limit_options(order))
+}
+
+/// Serializes a working order of a full book dump as a line of `key=value` fields: where
+/// it waits (`resting`, `stop` or `held`), its order id and owner, its filled and
+/// remaining quantity (and what of that is shown, for a resting order) and last the
+/// request that would enter it as it is now. A held amend has no quantities and names the
+/// order it amends.
+pub fn serialize_working_order(working: &WorkingOrder) -> String {
+    let (state, order, request) = match working {
+        WorkingOrder::Resting(order) => ("resting", Some(*order as &dyn Order), format_limit(order)),
+        WorkingOrder::Stop(PendingStop::Stop(stop)) => ("stop", Some(stop.order() as &dyn Order), format_stop(stop)),
+        WorkingOrder::Stop(PendingStop::StopLimit(stop)) => ("stop", Some(stop.order() as &dyn Order), format_stop_limit(stop)),
+        WorkingOrder::Held(request) => ("held", request.new_order(), format_request(request).unwrap_or_default()),
+    };
+
+    let mut line = format!("working state={state}");
+    if let Some(order) = order {
+        line.push_str(&format!(" order_id={} owner={} cum_qty={} leaves_qty={}", order.order_id(), order.client().owner(), order.fill_size(), order.remaining_size()));
+    } else if let WorkingOrder::Held(Orders::Amend(amend)) = working {
+        line.push_str(&format!(" order_id={} owner={}", amend.order_id(), amend.client().owner()));
+    }
+    if let WorkingOrder::Resting(order) = working {
+        line.push_str(&format!(" visible_qty={}", order.visible_size()));
+    }
+    line.push_str(&format!(" request={request}"));
+    line
+}
+
+fn time_in_force(time_in_force: TimeInForce) -> &'static str {
+    match time_in_force {
+        TimeInForce::Gtc => "gtc",
+        TimeInForce::Day => "day",
+        TimeInForce::Ioc => "ioc",
+        TimeInForce::Fok => "fok",
+    }
+}
+
+fn market_options(order: &MarketOrder) -> String {
+    let mut options = format!(" {}", time_in_force(order.time_in_force()));
+    match order.protection() {
+        Some(PriceProtection::Limit(price)) => options.push_str(&format!(" protect={price}")),
+        Some(PriceProtection::BasisPoints(bps)) => options.push_str(&format!(" protect={}.{:02}%", bps / 100, bps % 100)),
+        None => {},
+    }
+    options.push_str(&account_options(order));
+    options
+}
+
+fn limit_options(order: &LimitOrder) -> String {
+    let mut options = format!(" {}", time_in_force(order.time_in_force()));
+    if let Some(display_size) = order.display_size() {
+        options.push_str(&format!(" display={display_size}"));
+    }
+    match order.post_only() {
+        Some(PostOnly::Reject) => options.push_str(" postonly"),
+        Some(PostOnly::Reprice) => options.push_str(" postonly=reprice"),
+        None => {},
+    }
+    if order.is_hidden() {
+        options.push_str(" hidden");
+    }
+    options.push_str(&account_options(order));
+    options
+}
+
+fn account_options(order: &dyn Order) -> String {
+    let Some(account) = order.account() else {
+        return String::new();
+    };
+    let stp = match order.self_trade_prevention() {
+        SelfTradePrevention::CancelNewest => "cn",
+        SelfTradePrevention::CancelOldest => "co",
+        SelfTradePrevention::CancelBoth => "cb",
+        SelfTradePrevention::DecrementAndCancel => "dc",
+    };
+    format!(" account={account} stp={stp}")
+}
+
+/// Serializes an execution report as a single line of `key=value` fields for the TCP clients.
+pub fn serialize_report(report: &ExecutionReport) -> String {
+    let exec_type = match report {
+        ExecutionReport::New(_) => "NEW",
+        ExecutionReport::PartialFill(_) => "PARTIAL_FILL",
+        ExecutionReport::Fill(_) => "FILL",
+        ExecutionReport::Replaced(_) => "REPLACED",
+        ExecutionReport::Restated(_) => "RESTATED",
+        ExecutionReport::Canceled(_, _) => "CANCELED",
+        ExecutionReport::Rejected(_, _) => "REJECTED",
+        ExecutionReport::Expired(_) => "EXPIRED",
+    };
+    let details = report.details();
+
+    let mut line = format!(
+        "exec_type={} exec_id={}-{} order_id={} client_order_id={} symbol={} last_qty={} last_price={} cum_qty={} leaves_qty={} timestamp={}",
+        exec_type,
+        details.symbol(),
+        details.exec_id(),
+        details.order_id(),
+        details.client_order_id(),
+        details.symbol(),
+        details.last_qty(),
+        details.last_price(),
+        details.cum_qty(),
+        details.leaves_qty(),
+        details.timestamp().to_rfc3339(),
+    );
+    match report {
+        ExecutionReport::Rejected(_, reason) => line.push_str(&format!(" reason={}", reason.code())),
+        ExecutionReport::Canceled(_, reason) => line.push_str(&format!(" reason={}", reason.code())),
+        _ => {},
+    }
+    line
+}
+
+/// Serializes a depth snapshot as a single line of `key=value` fields. Each side is a
+/// comma separated list of `price:quantity:orders` levels, best price first.
+pub fn serialize_depth(depth: &Depth) -> String {
+    let side = |levels: &[DepthLevel]| {
+        levels
+            .iter()
+            .map(|level| format!("{}:{}:{}", level.price(), level.quantity(), level.order_count()))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    format!(
+        "book symbol={} seq={} bids={} asks={} timestamp={}",
+        depth.symbol(),
+        depth.seq(),
+        side(depth.bids()),
+        side(depth.asks()),
+        depth.timestamp().to_rfc3339(),
+    )
+}
+
+fn serialize_side(side: MarketSide) -> &'static str {
+    match side {
+        MarketSide::Bid => "buy",
+        MarketSide::Ask => "sell",
+    }
+}
+
+/// Serializes a trade print as a single line of `key=value` fields. Like exec ids, trade
+/// ids are numbered per book and go out prefixed with the symbol.
+pub fn serialize_trade(trade: &Trade) -> String {
+    format!(
+        "trade_id={}-{} symbol={} price={} size={} aggressor={} maker_order_id={} taker_order_id={} timestamp={}",
+        trade.symbol(),
+        trade.trade_id(),
+        trade.symbol(),
+        trade.price(),
+        trade.size(),
+        trade.aggressor_side().map_or("none", serialize_side),
+        trade.maker_order_id(),
+        trade.taker_order_id(),
+        trade.timestamp().to_rfc3339(),
+    )
+}
+
+/// Serializes a feed update as a single line of `key=value` fields, starting with the
+/// kind of update.
+pub fn serialize_market_data(update: &MarketData) -> String {
+    match update.event() {
+        MarketDataEvent::Level(level_update) => {
+            let action = match level_update.action() {
+                LevelAction::Add => "add",
+                LevelAction::Change => "change",
+                LevelAction::Delete => "delete",
+            };
+            let level = level_update.level();
+            format!(
+                "level symbol={} seq={} side={} action={} price={} qty={} orders={} timestamp={}",
+                update.symbol(),
+                update.seq(),
+                serialize_side(level_update.side()),
+                action,
+                level.price(),
+                level.quantity(),
+                level.order_count(),
+                update.timestamp().to_rfc3339(),
+            )
+        },
+        MarketDataEvent::Trade(trade) => format!("trade seq={} {}", update.seq(), serialize_trade(trade)),
+        MarketDataEvent::Phase(phase) => format!(
+            "phase symbol={} seq={} phase={} timestamp={}",
+            update.symbol(),
+            update.seq(),
+            phase.code(),
+            update.timestamp().to_rfc3339(),
+        ),
+        MarketDataEvent::Indicative(indicative) => format!(
+            "indicative symbol={} seq={} price={} volume={} timestamp={}",
+            update.symbol(),
+            update.seq(),
+            indicative.price().map_or("none".to_string(), |price| price.to_string()),
+            indicative.volume(),
+            update.timestamp().to_rfc3339(),
+        ),
+        MarketDataEvent::Order(order_update) => {
+            let action = match order_update.action() {
+                OrderAction::Add => "add",
+                OrderAction::Modify => "modify",
+                OrderAction::Execute => "execute",
+                OrderAction::Delete => "delete",
+            };
+            format!(
+                "order symbol={} seq={} action={} order_id={} side={} price={} qty={} timestamp={}",
+                update.symbol(),
+                update.seq(),
+                action,
+                order_update.order_id(),
+                serialize_side(order_update.side()),
+                order_update.price(),
+                order_update.quantity(),
+                update.timestamp().to_rfc3339(),
+            )
+        },
+    }
+}
+
+/// Serializes an order-by-order snapshot as a single line of `key=value` fields. Each
+/// side is a comma separated list of `price:order_id:quantity` orders, best price first
+/// and in queue order within a price.
+pub fn serialize_order_snapshot(snapshot: &OrderSnapshot) -> String {
+    let side = |orders: &[QueuedOrder]| {
+        orders
+            .iter()
+            .map(|order| format!("{}:{}:{}", order.price(), order.order_id(), order.quantity()))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    format!(
+        "orders symbol={} seq={} bids={} asks={} timestamp={}",
+        snapshot.symbol(),
+        snapshot.seq(),
+        side(snapshot.bids()),
+        side(snapshot.asks()),
+        snapshot.timestamp().to_rfc3339(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protection_percentages() {
+        assert_eq!(parse_protection("2.5%"), Ok(PriceProtection::BasisPoints(250)));
+        assert_eq!(parse_protection("0.05%"), Ok(PriceProtection::BasisPoints(5)));
+        assert_eq!(parse_protection("100%"), Ok(PriceProtection::BasisPoints(10_000)));
+        assert_eq!(parse_protection("101.25"), Ok(PriceProtection::Limit("101.25".parse().unwrap())));
+    }
+
+    #[test]
+    fn protection_overflow_rejected() {
+        let invalid = ["1844674407370955161%", "18446744073709551615%", "100.01%", "250%", "%", ".5%", "+5%", "1.234%", "1.x%", "0"];
+        for value in invalid {
+            assert_eq!(parse_protection(value), Err(ParseError::InvalidProtection), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn protection_in_order_line() {
+        let (client, _rx) = crate::testing::client(1);
+        let line = "buy market T 10 a protect=1844674407370955161%";
+        assert_eq!(parse_request(line, client, DateTime::UNIX_EPOCH).err(), Some(ParseError::InvalidProtection));
+    }
+
+    #[test]
+    fn login_lines() {
+        assert_eq!(parse_login("LOGIN desk-1\n"), Some(Ok("desk-1")));
+        assert_eq!(parse_login("login"), Some(Err(ParseError::InvalidLogin)));
+        assert_eq!(parse_login("login a b"), Some(Err(ParseError::InvalidLogin)));
+        assert_eq!(parse_login("login a/b"), Some(Err(ParseError::InvalidLogin)));
+        assert_eq!(parse_login("buy limit T 100.00 5 a"), None);
+        assert_eq!(parse_login(""), None);
+    }
+
+    #[test]
+    fn exec_and_trade_ids_prefixed_with_symbol() {
+        let fill = |symbol: &str| {
+            let details = crate::execution_report::ExecutionDetails::new(1, 7, "c1".to_string(), symbol.to_string(), 5, "100.00".parse().unwrap(), 5, 0, DateTime::UNIX_EPOCH);
+            serialize_report(&ExecutionReport::Fill(details))
+        };
+        assert!(fill("AAPL").starts_with("exec_type=FILL exec_id=AAPL-1 order_id=7 "));
+        assert!(fill("MSFT").starts_with("exec_type=FILL exec_id=MSFT-1 "));
+
+        let trade = Trade::new(6, "BRK.B".to_string(), "101.00".parse().unwrap(), 3, Some(MarketSide::Bid), 4, 9, DateTime::UNIX_EPOCH);
+        assert!(serialize_trade(&trade).starts_with("trade_id=BRK.B-6 symbol=BRK.B "));
+    }
+}
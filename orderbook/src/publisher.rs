@@ -0,0 +1,209 @@
+use std::{collections::{HashMap, HashSet}, net::SocketAddr};
+use tokio::sync::mpsc;
+
+use crate::{
+    execution_report::RejectReason,
+    market_data::{Depth, Feed, LevelBook, MarketData, OrderSnapshot, QueueBook},
+};
+
+/// Everything a market data subscriber receives.
+#[derive(Debug, Clone)]
+pub enum FeedMessage {
+    Snapshot(Depth),
+    OrderSnapshot(OrderSnapshot),
+    Update(MarketData),
+    Rejected(String, RejectReason),
+}
+
+/// Requests from the connections of market data subscribers.
+#[derive(Debug)]
+pub enum FeedRequest {
+    Connect(SocketAddr, mpsc::Sender<FeedMessage>),
+    Subscribe(SocketAddr, String, Feed),
+    Snapshot(SocketAddr, String, Feed),
+    Disconnect(SocketAddr),
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    tx: mpsc::Sender<FeedMessage>,
+    feeds: HashSet<(String, Feed)>,
+}
+
+/// Fans the feeds of every book out to their subscribers. The publisher rebuilds the
+/// levels and order queues of each book from its updates, so it can hand out snapshots
+/// without asking the book, and every snapshot is followed by exactly the updates that
+/// come after it.
+///
+/// Subscribers are fed through bounded queues: one that falls behind loses updates
+/// instead of holding up the others, notices the jump in sequence numbers and asks for
+/// a new snapshot.
+#[derive(Debug)]
+pub struct MarketDataPublisher {
+    books: HashMap<String, (LevelBook, QueueBook)>,
+    subscribers: HashMap<SocketAddr, Subscriber>,
+}
+
+impl MarketDataPublisher {
+    pub fn new() -> Self {
+        MarketDataPublisher {
+            books: HashMap::new(),
+            subscribers: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, symbol: String) {
+        self.books.insert(symbol.clone(), (LevelBook::new(symbol.clone()), QueueBook::new(symbol)));
+    }
+
+    pub fn handle_request(&mut self, request: FeedRequest) {
+        match request {
+            FeedRequest::Connect(sockaddr, tx) => {
+                self.subscribers.insert(sockaddr, Subscriber { tx, feeds: HashSet::new() });
+            },
+            FeedRequest::Subscribe(sockaddr, symbol, feed) => {
+                if !self.send_snapshot(sockaddr, &symbol, feed) {
+                    return;
+                }
+                if let Some(subscriber) = self.subscribers.get_mut(&sockaddr) {
+                    subscriber.feeds.insert((symbol, feed));
+                }
+            },
+            FeedRequest::Snapshot(sockaddr, symbol, feed) => {
+                self.send_snapshot(sockaddr, &symbol, feed);
+            },
+            FeedRequest::Disconnect(sockaddr) => {
+                self.subscribers.remove(&sockaddr);
+            },
+        }
+    }
+
+    /// Applies an update to the levels or queues of its book and forwards it to every
+    /// subscriber of its feed.
+    pub fn publish(&mut self, update: MarketData) {
+        let Some((levels, queues)) = self.books.get_mut(update.symbol()) else {
+            eprintln!("Market data for unregistered symbol {}", update.symbol());
+            return;
+        };
+        let applied = match update.feed() {
+            Feed::Levels => levels.apply(&update),
+            Feed::Orders => queues.apply(&update),
+        };
+        if let Err(gap) = applied {
+            eprintln!("Market data for {} out of sequence: {gap}", update.symbol());
+            return;
+        }
+
+        let key = (update.symbol().clone(), update.feed());
+        for (sockaddr, subscriber) in &self.subscribers {
+            if subscriber.feeds.contains(&key) {
+                Self::send(*sockaddr, &subscriber.tx, FeedMessage::Update(update.clone()));
+            }
+        }
+    }
+
+    /// Sends the current levels or queues of `symbol`, or a reject if no book trades it.
+    /// Returns true if the symbol is known.
+    fn send_snapshot(&self, sockaddr: SocketAddr, symbol: &String, feed: Feed) -> bool {
+        let Some(subscriber) = self.subscribers.get(&sockaddr) else {
+            return false;
+        };
+        match self.books.get(symbol) {
+            Some((levels, queues)) => {
+                let snapshot = match feed {
+                    Feed::Levels => FeedMessage::Snapshot(levels.snapshot()),
+                    Feed::Orders => FeedMessage::OrderSnapshot(queues.snapshot()),
+                };
+                Self::send(sockaddr, &subscriber.tx, snapshot);
+                true
+            },
+            None => {
+                Self::send(sockaddr, &subscriber.tx, FeedMessage::Rejected(symbol.clone(), RejectReason::UnknownSymbol));
+                false
+            },
+        }
+    }
+
+    /// Never waits on a subscriber: if its queue is full the message is dropped.
+    fn send(sockaddr: SocketAddr, tx: &mpsc::Sender<FeedMessage>, message: FeedMessage) {
+        if let Err(e) = tx.try_send(message) {
+            eprintln!("Dropped market data for subscriber {sockaddr}: {e}");
+        }
+    }
+}
+
+impl Default for MarketDataPublisher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        market_data::{DepthLevel, LevelAction, LevelUpdate, MarketDataEvent},
+        orders::MarketSide,
+    };
+    use chrono::DateTime;
+
+    fn level(seq: u64, quantity: usize) -> MarketData {
+        let level = DepthLevel::new("100.00".parse().unwrap(), quantity, 1);
+        let action = if seq == 1 { LevelAction::Add } else { LevelAction::Change };
+        MarketData::new("T".to_string(), seq, MarketDataEvent::Level(LevelUpdate::new(MarketSide::Bid, action, level)), DateTime::UNIX_EPOCH)
+    }
+
+    fn subscriber(publisher: &mut MarketDataPublisher, port: u16, capacity: usize) -> (SocketAddr, mpsc::Receiver<FeedMessage>) {
+        let sockaddr = SocketAddr::from(([127, 0, 0, 1], port));
+        let (tx, rx) = mpsc::channel(capacity);
+        publisher.handle_request(FeedRequest::Connect(sockaddr, tx));
+        publisher.handle_request(FeedRequest::Subscribe(sockaddr, "T".to_string(), Feed::Levels));
+        (sockaddr, rx)
+    }
+
+    /// Sequence numbers of the messages waiting for a subscriber: the snapshot's, then
+    /// each update's.
+    fn received(rx: &mut mpsc::Receiver<FeedMessage>) -> Vec<(&'static str, u64)> {
+        let mut messages = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            messages.push(match message {
+                FeedMessage::Snapshot(depth) => ("snapshot", depth.seq()),
+                FeedMessage::Update(update) => ("update", update.seq()),
+                other => panic!("{other:?}"),
+            });
+        }
+        messages
+    }
+
+    #[test]
+    fn snapshot_then_following_updates() {
+        let mut publisher = MarketDataPublisher::new();
+        publisher.register("T".to_string());
+        publisher.publish(level(1, 5));
+        publisher.publish(level(2, 6));
+
+        let (_, mut rx) = subscriber(&mut publisher, 1, 16);
+        publisher.publish(level(3, 7));
+        publisher.publish(level(4, 8));
+        assert_eq!(received(&mut rx), [("snapshot", 2), ("update", 3), ("update", 4)]);
+
+        // Out of sequence: neither applied nor forwarded.
+        publisher.publish(level(6, 9));
+        assert_eq!(received(&mut rx), []);
+    }
+
+    #[test]
+    fn slow_subscriber_resumes_from_new_snapshot() {
+        let mut publisher = MarketDataPublisher::new();
+        publisher.register("T".to_string());
+        let (sockaddr, mut rx) = subscriber(&mut publisher, 1, 2);
+        for seq in 1..=4 {
+            publisher.publish(level(seq, seq as usize));
+        }
+        assert_eq!(received(&mut rx), [("snapshot", 0), ("update", 1)]);
+
+        publisher.handle_request(FeedRequest::Snapshot(sockaddr, "T".to_string(), Feed::Levels));
+        publisher.publish(level(5, 5));
+        assert_eq!(received(&mut rx), [("snapshot", 4), ("update", 5)]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{collections::{HashMap, HashSet}, net::SocketAddr};
+use tokio::sync::mpsc;
+
+use crate::{
+    client_handler::Owner,
+    execution_report::{ExecutionDetails, ExecutionReport, RejectReason},
+    instrument::Instrument,
+    orders::Orders,
+    price::Price,
+};
+
+/// Requests from client connections.
+#[derive(Debug)]
+pub enum ClientRequest {
+    Order(Orders),
+    /// The connection closed, ending its session unless it logged in.
+    Disconnect(SocketAddr),
+}
+
+impl From<Orders> for ClientRequest {
+    fn from(order: Orders) -> Self {
+        ClientRequest::Order(order)
+    }
+}
+
+/// Routes every order to the book of its symbol, after checking it against the
+/// instrument's reference data. Each book is reached through its own channel, so books
+/// can run on separate threads and independent symbols match in parallel.
+///
+/// The registry is the single sequencer of the server: it assigns exchange order ids in
+/// arrival order, which keeps them unique across books. It also makes sure no session
+/// uses a client order id twice, whatever the symbols. The session of a login spans its
+/// connections, that of any other client ends with its connection.
+#[derive(Debug)]
+pub struct BookRegistry {
+    books: HashMap<String, (Instrument, mpsc::UnboundedSender<Orders>)>,
+    next_order_id: u64,
+    /// Client order ids used by each login and each open connection without one.
+    client_order_ids: HashMap<Owner, HashSet<String>>,
+}
+
+impl BookRegistry {
+    pub fn new() -> Self {
+        BookRegistry {
+            books: HashMap::new(),
+            next_order_id: 1,
+            client_order_ids: HashMap::new(),
+        }
+    }
+
+    /// Registers the channel of the book trading `instrument`, replacing any previous one
+    /// for the same symbol.
+    pub fn register(&mut self, instrument: Instrument, tx_book: mpsc::UnboundedSender<Orders>) {
+        self.books.insert(instrument.symbol().clone(), (instrument, tx_book));
+    }
+
+    pub fn instrument(&self, symbol: &str) -> Option<&Instrument> {
+        self.books.get(symbol).map(|(instrument, _)| instrument)
+    }
+
+    pub fn contains(&self, symbol: &str) -> bool {
+        self.books.contains_key(symbol)
+    }
+
+    /// Continues numbering after `order_id`, the last id of an earlier run, so that
+    /// orders recovered from a journal keep their ids unique. Never goes backwards.
+    pub fn skip_order_ids(&mut self, order_id: u64) {
+        self.next_order_id = self.next_order_id.max(order_id + 1);
+    }
+
+    /// Marks `client_order_id` as used by `owner`, for orders recovered from a journal.
+    pub fn reserve_client_order_id(&mut self, owner: Owner, client_order_id: String) {
+        self.client_order_ids.entry(owner).or_default().insert(client_order_id);
+    }
+
+    /// Registered symbols, in no particular order.
+    pub fn symbols(&self) -> impl Iterator<Item = &String> {
+        self.books.keys()
+    }
+
+    pub fn handle_request(&mut self, request: ClientRequest) {
+        match request {
+            ClientRequest::Order(order) => self.route(order),
+            ClientRequest::Disconnect(sockaddr) => {
+                self.client_order_ids.remove(&Owner::Connection(sockaddr));
+            },
+        }
+    }
+
+    /// Assigns an order id to new orders and forwards the request to its book. Requests
+    /// for a symbol without a book, off the instrument's price and size grid, or reusing
+    /// a client order id of the session are rejected straight away.
+    pub fn route(&mut self, mut order: Orders) {
+        let Some((instrument, tx_book)) = self.books.get(order.symbol()) else {
+            Self::reject(&order, RejectReason::UnknownSymbol);
+            return;
+        };
+        if let Err(reason) = instrument.validate(&mut order) {
+            Self::reject(&order, reason);
+            return;
+        }
+        if let Some(new_order) = order.new_order() {
+            let used = self.client_order_ids.entry(new_order.client().owner().clone()).or_default();
+            if !used.insert(new_order.client_order_id().clone()) {
+                Self::reject(&order, RejectReason::DuplicateClientOrderId);
+                return;
+            }
+        }
+
+        if let Some(new_order) = order.new_order_mut() {
+            new_order.set_order_id(self.next_order_id);
+            self.next_order_id += 1;
+        }
+
+        if let Err(e) = tx_book.send(order) {
+            eprintln!("Error sending order to OrderBook: {e}");
+        }
+    }
+
+    /// Reject for a request that never reached a book, so it carries no exec id.
+    fn reject(order: &Orders, reason: RejectReason) {
+        let (order_id, client_order_id) = match order {
+            Orders::Cancel(cancel_order) => (cancel_order.order_id(), String::new()),
+            Orders::Amend(amend_order) => (amend_order.order_id(), String::new()),
+            _ => (0, order.new_order().map(|o| o.client_order_id().clone()).unwrap_or_default()),
+        };
+
+        let details = ExecutionDetails::new(0, order_id, client_order_id, order.symbol().clone(), 0, Price::default(), 0, 0, *order.timestamp());
+        if let Err(e) = order.client().tx().send(ExecutionReport::Rejected(details, reason).into()) {
+            eprintln!("Error writing to channel: {e}");
+        }
+    }
+}
+
+impl Default for BookRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client_handler::{Client, ClientMessage, Logins}, orders::{LimitOrder, MarketSide}, testing::{client, login, reports}};
+    use chrono::DateTime;
+
+    fn registry(symbols: &[&str]) -> (BookRegistry, mpsc::UnboundedReceiver<Orders>) {
+        let mut registry = BookRegistry::new();
+        let (tx_book, rx_book) = mpsc::unbounded_channel();
+        for symbol in symbols {
+            let instrument = Instrument::new(symbol.to_string(), "0.01".parse().unwrap(), 1, 1, 1000, "100.00".parse().unwrap(), 5000, 0);
+            registry.register(instrument, tx_book.clone());
+        }
+        (registry, rx_book)
+    }
+
+    fn limit(client: &Client, symbol: &str, client_order_id: &str) -> Orders {
+        LimitOrder::new(DateTime::UNIX_EPOCH, symbol.to_string(), 10, 0, MarketSide::Bid, "100.00".parse().unwrap(), client.clone(), client_order_id.to_string()).into()
+    }
+
+    fn rejected(rx: &mut mpsc::UnboundedReceiver<ClientMessage>) -> Vec<RejectReason> {
+        reports(rx)
+            .into_iter()
+            .filter_map(|report| match report {
+                ExecutionReport::Rejected(_, reason) => Some(reason),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn duplicate_client_order_id_rejected_across_symbols() {
+        let (mut registry, mut rx_book) = registry(&["A", "B"]);
+        let (x, mut rx_x) = client(1);
+        let (y, mut rx_y) = client(2);
+
+        registry.handle_request(limit(&x, "A", "c1").into());
+        registry.handle_request(limit(&x, "B", "c1").into());
+        registry.handle_request(limit(&y, "B", "c1").into());
+
+        assert_eq!(rejected(&mut rx_x), vec![RejectReason::DuplicateClientOrderId]);
+        assert!(rejected(&mut rx_y).is_empty());
+        let routed: Vec<u64> = std::iter::from_fn(|| rx_book.try_recv().ok())
+            .map(|order| order.new_order().unwrap().order_id())
+            .collect();
+        assert_eq!(routed, vec![1, 2]);
+    }
+
+    #[test]
+    fn client_order_ids_pruned_on_disconnect() {
+        let (mut registry, mut rx_book) = registry(&["A"]);
+        let (x, mut rx_x) = client(1);
+
+        registry.handle_request(limit(&x, "A", "c1").into());
+        registry.handle_request(ClientRequest::Disconnect(SocketAddr::from(([127, 0, 0, 1], 1))));
+        registry.handle_request(limit(&x, "A", "c1").into());
+
+        assert!(rejected(&mut rx_x).is_empty());
+        assert_eq!(std::iter::from_fn(|| rx_book.try_recv().ok()).count(), 2);
+    }
+
+    #[test]
+    fn client_order_ids_of_a_login_outlive_its_connection() {
+        let (mut registry, _rx_book) = registry(&["A"]);
+        let logins = Logins::new();
+        let (x, _rx_x) = login(&logins, "alice", 1);
+
+        registry.handle_request(limit(&x, "A", "c1").into());
+        registry.handle_request(ClientRequest::Disconnect(SocketAddr::from(([127, 0, 0, 1], 1))));
+        logins.logout("alice", SocketAddr::from(([127, 0, 0, 1], 1)));
+        let (y, mut rx_y) = login(&logins, "alice", 2);
+        registry.handle_request(limit(&y, "A", "c1").into());
+
+        registry.reserve_client_order_id(Owner::login("alice").unwrap(), "c2".to_string());
+        registry.handle_request(limit(&y, "A", "c2").into());
+
+        assert_eq!(rejected(&mut rx_y), vec![RejectReason::DuplicateClientOrderId, RejectReason::DuplicateClientOrderId]);
+    }
+}
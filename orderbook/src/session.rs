@@ -0,0 +1,183 @@
+use chrono::{DateTime, Days, NaiveTime, TimeDelta, Utc};
+use std::io;
+
+/// Trading phases of a session day, in the order they follow each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TradingPhase {
+    /// Orders may be entered, amended and canceled, but nothing matches.
+    PreOpen,
+    /// Call before the open: orders accumulate and an indicative price is published,
+    /// then the book uncrosses.
+    OpeningAuction,
+    Continuous,
+    /// Call before the close, ending with an uncross like the opening auction.
+    ClosingAuction,
+    /// Only cancels are accepted.
+    Closed,
+    /// Short call entered when continuous trading would print outside the instrument's
+    /// volatility band. It uncrosses once its time is up.
+    VolatilityAuction,
+    /// Trading suspended by an operator until resumed. Nothing matches and no new order
+    /// enters the book, but cancels are still accepted.
+    Halted,
+}
+
+impl TradingPhase {
+    pub fn code(&self) -> &'static str {
+        match self {
+            TradingPhase::PreOpen => "PRE_OPEN",
+            TradingPhase::OpeningAuction => "OPENING_AUCTION",
+            TradingPhase::Continuous => "CONTINUOUS",
+            TradingPhase::ClosingAuction => "CLOSING_AUCTION",
+            TradingPhase::Closed => "CLOSED",
+            TradingPhase::VolatilityAuction => "VOLATILITY_AUCTION",
+            TradingPhase::Halted => "HALTED",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "PRE_OPEN" => Some(TradingPhase::PreOpen),
+            "OPENING_AUCTION" => Some(TradingPhase::OpeningAuction),
+            "CONTINUOUS" => Some(TradingPhase::Continuous),
+            "CLOSING_AUCTION" => Some(TradingPhase::ClosingAuction),
+            "CLOSED" => Some(TradingPhase::Closed),
+            "VOLATILITY_AUCTION" => Some(TradingPhase::VolatilityAuction),
+            "HALTED" => Some(TradingPhase::Halted),
+            _ => None,
+        }
+    }
+
+    /// Returns true for the phases in which orders rest without matching.
+    pub fn is_call(&self) -> bool {
+        matches!(self, TradingPhase::PreOpen | TradingPhase::OpeningAuction | TradingPhase::ClosingAuction | TradingPhase::VolatilityAuction)
+    }
+
+    pub fn is_auction(&self) -> bool {
+        matches!(self, TradingPhase::OpeningAuction | TradingPhase::ClosingAuction | TradingPhase::VolatilityAuction)
+    }
+}
+
+/// What a halted book does with new orders and amends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HaltPolicy {
+    /// Refuse them with a trading phase reject.
+    #[default]
+    Reject,
+    /// Acknowledge new orders and hold everything in arrival order, to be entered once
+    /// trading resumes.
+    Queue,
+}
+
+impl HaltPolicy {
+    /// Name of the policy as `HALT_ORDERS` takes it.
+    pub fn code(&self) -> &'static str {
+        match self {
+            HaltPolicy::Reject => "reject",
+            HaltPolicy::Queue => "queue",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "reject" => Some(HaltPolicy::Reject),
+            "queue" => Some(HaltPolicy::Queue),
+            _ => None,
+        }
+    }
+}
+
+/// Reads `HALT_ORDERS`, `reject` (default) or `queue`.
+pub fn halt_policy_from_env() -> io::Result<HaltPolicy> {
+    match std::env::var("HALT_ORDERS") {
+        Ok(s) => HaltPolicy::from_code(&s).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid HALT_ORDERS {s:?}: expected reject or queue"))),
+        Err(_) => Ok(HaltPolicy::default()),
+    }
+}
+
+/// Parses a volatility auction length given as a positive number of seconds.
+fn parse_volatility_auction(s: &str) -> Option<TimeDelta> {
+    s.parse::<i64>().ok().filter(|&secs| secs > 0).and_then(TimeDelta::try_seconds)
+}
+
+/// Reads `VOLATILITY_AUCTION_SECS`, the length of the volatility auction a circuit
+/// breaker starts, five minutes if unset.
+pub fn volatility_auction_from_env() -> io::Result<TimeDelta> {
+    match std::env::var("VOLATILITY_AUCTION_SECS") {
+        Ok(s) => parse_volatility_auction(&s).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid VOLATILITY_AUCTION_SECS {s:?}: expected a positive number of seconds"))),
+        Err(_) => Ok(TimeDelta::minutes(5)),
+    }
+}
+
+/// Daily start times (UTC) of every trading phase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TradingSchedule {
+    starts: [(NaiveTime, TradingPhase); 5],
+}
+
+impl TradingSchedule {
+    /// Returns `None` unless the phases start in order within the day.
+    pub fn new(pre_open: NaiveTime, opening_auction: NaiveTime, continuous: NaiveTime, closing_auction: NaiveTime, closed: NaiveTime) -> Option<Self> {
+        let starts = [
+            (pre_open, TradingPhase::PreOpen),
+            (opening_auction, TradingPhase::OpeningAuction),
+            (continuous, TradingPhase::Continuous),
+            (closing_auction, TradingPhase::ClosingAuction),
+            (closed, TradingPhase::Closed),
+        ];
+        starts
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0)
+            .then_some(TradingSchedule { starts })
+    }
+
+    /// Time the market closes every day.
+    pub fn close(&self) -> NaiveTime {
+        self.starts[4].0
+    }
+
+    /// Phase in force at `now`. Before the pre-open the market is still closed from the
+    /// previous day.
+    pub fn phase_at(&self, now: DateTime<Utc>) -> TradingPhase {
+        let time = now.time();
+        self.starts
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= time)
+            .map_or(TradingPhase::Closed, |&(_, phase)| phase)
+    }
+
+    /// Next phase change after `now` and the phase it starts.
+    pub fn next_change(&self, now: DateTime<Utc>) -> (DateTime<Utc>, TradingPhase) {
+        let today = now.date_naive();
+        match self.starts.iter().find(|(start, _)| today.and_time(*start).and_utc() > now) {
+            Some(&(start, phase)) => (today.and_time(start).and_utc(), phase),
+            None => {
+                let (start, phase) = self.starts[0];
+                (today.and_time(start).and_utc() + Days::new(1), phase)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halt_policy_codes() {
+        for policy in [HaltPolicy::Reject, HaltPolicy::Queue] {
+            assert_eq!(HaltPolicy::from_code(policy.code()), Some(policy));
+        }
+        assert_eq!(HaltPolicy::from_code("queu"), None);
+        assert_eq!(HaltPolicy::from_code("Queue"), None);
+    }
+
+    #[test]
+    fn volatility_auction_seconds() {
+        assert_eq!(parse_volatility_auction("60"), Some(TimeDelta::minutes(1)));
+        for s in ["", "0", "-5", "1.5", "60s", "99999999999999999999"] {
+            assert_eq!(parse_volatility_auction(s), None, "{s:?}");
+        }
+    }
+}
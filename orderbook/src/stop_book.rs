@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use crate::{
+    orders::{MarketSide, Order, StopLimitOrder, StopOrder, TimeInForce},
+    price::Price,
+};
+
+/// A stop or stop-limit order waiting in the trigger book.
+#[derive(Debug)]
+pub enum PendingStop {
+    Stop(StopOrder),
+    StopLimit(StopLimitOrder),
+}
+
+impl PendingStop {
+    pub fn trigger_price(&self) -> Price {
+        match self {
+            PendingStop::Stop(stop) => stop.trigger_price(),
+            PendingStop::StopLimit(stop) => stop.trigger_price(),
+        }
+    }
+
+    pub fn order(&self) -> &dyn Order {
+        match self {
+            PendingStop::Stop(stop) => stop.order(),
+            PendingStop::StopLimit(stop) => stop.order(),
+        }
+    }
+}
+
+/// Stop orders keyed by trigger price, kept apart from the limit book until the last
+/// traded price reaches them.
+#[derive(Debug, Default)]
+pub struct StopBook {
+    buy_stops: BTreeMap<Price, VecDeque<PendingStop>>,
+    sell_stops: BTreeMap<Price, VecDeque<PendingStop>>,
+    index: HashMap<u64, (MarketSide, Price)>,
+}
+
+impl StopBook {
+    pub fn new() -> Self {
+        StopBook {
+            buy_stops: BTreeMap::new(),
+            sell_stops: BTreeMap::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Returns true if a stop on `side` at `trigger_price` fires at `last_price`.
+    pub fn is_triggered(side: MarketSide, trigger_price: Price, last_price: Price) -> bool {
+        match side {
+            MarketSide::Bid => last_price >= trigger_price,
+            MarketSide::Ask => last_price <= trigger_price,
+        }
+    }
+
+    pub fn insert(&mut self, stop: PendingStop) {
+        let side = stop.order().side();
+        let trigger_price = stop.trigger_price();
+        self.index.insert(stop.order().order_id(), (side, trigger_price));
+
+        let stops = match side {
+            MarketSide::Bid => &mut self.buy_stops,
+            MarketSide::Ask => &mut self.sell_stops,
+        };
+        stops.entry(trigger_price).or_default().push_back(stop);
+    }
+
+    pub fn get(&self, order_id: u64) -> Option<&PendingStop> {
+        let (side, trigger_price) = self.index.get(&order_id)?;
+        let stops = match side {
+            MarketSide::Bid => &self.buy_stops,
+            MarketSide::Ask => &self.sell_stops,
+        };
+        stops.get(trigger_price)?
+            .iter()
+            .find(|stop| stop.order().order_id() == order_id)
+    }
+
+    pub fn remove(&mut self, order_id: u64) -> Option<PendingStop> {
+        let (side, trigger_price) = self.index.remove(&order_id)?;
+        let stops = match side {
+            MarketSide::Bid => &mut self.buy_stops,
+            MarketSide::Ask => &mut self.sell_stops,
+        };
+
+        let queue = stops.get_mut(&trigger_price)?;
+        let position = queue.iter().position(|stop| stop.order().order_id() == order_id)?;
+        let stop = queue.remove(position);
+        if queue.is_empty() {
+            stops.remove(&trigger_price);
+        }
+        stop
+    }
+
+    /// Removes every stop fired by `last_price`, in the order they must be entered: buy
+    /// stops from the lowest trigger up, then sell stops from the highest trigger down,
+    /// and by arrival within a trigger price.
+    pub fn take_triggered(&mut self, last_price: Price) -> Vec<PendingStop> {
+        let mut triggered = Vec::new();
+
+        let buy_triggers: Vec<Price> = self.buy_stops.range(..=last_price).map(|(&price, _)| price).collect();
+        for price in buy_triggers {
+            triggered.extend(self.buy_stops.remove(&price).unwrap());
+        }
+
+        let sell_triggers: Vec<Price> = self.sell_stops.range(last_price..).rev().map(|(&price, _)| price).collect();
+        for price in sell_triggers {
+            triggered.extend(self.sell_stops.remove(&price).unwrap());
+        }
+
+        for stop in &triggered {
+            self.index.remove(&stop.order().order_id());
+        }
+        triggered
+    }
+
+    /// Removes every stop with a DAY time in force, by order id.
+    pub fn take_day_orders(&mut self) -> Vec<PendingStop> {
+        self.take_where(|stop| stop.order().time_in_force() == TimeInForce::Day)
+    }
+
+    /// Every stop, by order id.
+    pub fn iter(&self) -> impl Iterator<Item = &PendingStop> {
+        let mut stops: Vec<&PendingStop> = self.buy_stops.values().chain(self.sell_stops.values()).flatten().collect();
+        stops.sort_unstable_by_key(|stop| stop.order().order_id());
+        stops.into_iter()
+    }
+
+    /// Number of stops that `filter` picks.
+    pub fn count_where(&self, filter: impl Fn(&PendingStop) -> bool) -> usize {
+        self.buy_stops.values().chain(self.sell_stops.values()).flatten().filter(|stop| filter(stop)).count()
+    }
+
+    /// Removes every stop that `filter` picks, by order id.
+    pub fn take_where(&mut self, filter: impl Fn(&PendingStop) -> bool) -> Vec<PendingStop> {
+        let mut taken: Vec<u64> = self.buy_stops
+            .values()
+            .chain(self.sell_stops.values())
+            .flatten()
+            .filter(|stop| filter(stop))
+            .map(|stop| stop.order().order_id())
+            .collect();
+        taken.sort_unstable();
+
+        taken
+            .into_iter()
+            .filter_map(|order_id| self.remove(order_id))
+            .collect()
+    }
+}
@@ -0,0 +1,33 @@
+//! Fixtures shared by the unit tests.
+
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+
+use crate::{
+    client_handler::{Client, ClientMessage, Logins},
+    execution_report::ExecutionReport,
+};
+
+/// Client on a loopback connection from `port`, with the receiving end of its channel.
+pub fn client(port: u16) -> (Client, mpsc::UnboundedReceiver<ClientMessage>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (Client::new(tx, SocketAddr::from(([127, 0, 0, 1], port))), rx)
+}
+
+/// Client logged in to `logins` as `name` from loopback `port`, with the receiving end
+/// of its channel.
+pub fn login(logins: &Logins, name: &str, port: u16) -> (Client, mpsc::UnboundedReceiver<ClientMessage>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (logins.login(name, SocketAddr::from(([127, 0, 0, 1], port)), tx).unwrap(), rx)
+}
+
+/// Execution reports sent to a client so far.
+pub fn reports(rx: &mut mpsc::UnboundedReceiver<ClientMessage>) -> Vec<ExecutionReport> {
+    let mut reports = Vec::new();
+    while let Ok(message) = rx.try_recv() {
+        if let ClientMessage::Report(report) = message {
+            reports.push(report);
+        }
+    }
+    reports
+}
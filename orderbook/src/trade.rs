@@ -0,0 +1,69 @@
+use chrono::{DateTime, Utc};
+
+use crate::{orders::MarketSide, price::Price};
+
+/// A single execution between a resting (maker) order and an incoming (taker) order. In
+/// an auction uncross both orders were resting, so there is no aggressor and the order
+/// that entered the book first is reported as the maker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trade {
+    trade_id: u64,
+    symbol: String,
+    price: Price,
+    size: usize,
+    aggressor_side: Option<MarketSide>,
+    maker_order_id: u64,
+    taker_order_id: u64,
+    timestamp: DateTime<Utc>,
+}
+
+impl Trade {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(trade_id: u64, symbol: String, price: Price, size: usize, aggressor_side: Option<MarketSide>, maker_order_id: u64, taker_order_id: u64, timestamp: DateTime<Utc>) -> Self {
+        Trade {
+            trade_id,
+            symbol,
+            price,
+            size,
+            aggressor_side,
+            maker_order_id,
+            taker_order_id,
+            timestamp,
+        }
+    }
+
+    /// Sequence number of the trade within its book, unique across books together with
+    /// the symbol.
+    pub fn trade_id(&self) -> u64 {
+        self.trade_id
+    }
+
+    pub fn symbol(&self) -> &String {
+        &self.symbol
+    }
+
+    pub fn price(&self) -> Price {
+        self.price
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Side of the incoming order that took liquidity, `None` for auction trades.
+    pub fn aggressor_side(&self) -> Option<MarketSide> {
+        self.aggressor_side
+    }
+
+    pub fn maker_order_id(&self) -> u64 {
+        self.maker_order_id
+    }
+
+    pub fn taker_order_id(&self) -> u64 {
+        self.taker_order_id
+    }
+
+    pub fn timestamp(&self) -> &DateTime<Utc> {
+        &self.timestamp
+    }
+}